@@ -1,38 +1,152 @@
-use std::{num::{ParseFloatError, ParseIntError}, array::TryFromSliceError, str::ParseBoolError};
+use std::{array::TryFromSliceError, convert::Infallible, fmt};
+
+/// a byte range into the expression string, `end` is exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
 
 #[derive(Debug)]
 pub enum Error {
-    UnkownCharacter(char),
-    UnexpectedToken,
-    NoIdentifierMatch,
-    InvalidToken,
-    InvalidNamespace,
+    UnkownCharacter(char, Span),
+    UnexpectedToken(Span),
+    NoIdentifierMatch(Span),
+    InvalidToken(Span),
+    InvalidNamespace(Span),
+    UnmatchedBracket(Span),
+    UnexpectedEnd(Span),
+    InvalidArgCount(Span),
+    RecursiveDefinition(Span),
+    Redeclaration(Span),
+    ReadBeforeAssignment(Span),
+    TooDeep(Span),
     InvalidArgs,
     InvalidVariable,
     NotCompiled,
     AlreadyCompiled,
     InvalidIndex,
     EmptyExpression,
+    LengthMismatch,
+    ResultCount,
 }
 
-impl From<ParseFloatError> for Error {
-    fn from(_: ParseFloatError) -> Self {
-        Self::InvalidToken
+impl Error {
+    /// returns the span of the expression string that caused the error, if the error has one
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Self::UnkownCharacter(_, span)
+            | Self::UnexpectedToken(span)
+            | Self::NoIdentifierMatch(span)
+            | Self::InvalidToken(span)
+            | Self::InvalidNamespace(span)
+            | Self::UnmatchedBracket(span)
+            | Self::UnexpectedEnd(span)
+            | Self::InvalidArgCount(span)
+            | Self::RecursiveDefinition(span)
+            | Self::Redeclaration(span)
+            | Self::ReadBeforeAssignment(span)
+            | Self::TooDeep(span) => Some(*span),
+            _ => None,
+        }
     }
-}
-impl From<ParseIntError> for Error {
-    fn from(_: ParseIntError) -> Self {
-        Self::InvalidToken
+    /// replaces the span of the error, errors without a span are returned unchanged
+    ///
+    /// used for errors created without knowledge of the expression string,
+    /// like the ones returned by `Function::from_string`
+    pub(crate) fn with_span(mut self, span: Span) -> Self {
+        match &mut self {
+            Self::UnkownCharacter(_, old)
+            | Self::UnexpectedToken(old)
+            | Self::NoIdentifierMatch(old)
+            | Self::InvalidToken(old)
+            | Self::InvalidNamespace(old)
+            | Self::UnmatchedBracket(old)
+            | Self::UnexpectedEnd(old)
+            | Self::InvalidArgCount(old)
+            | Self::RecursiveDefinition(old)
+            | Self::Redeclaration(old)
+            | Self::ReadBeforeAssignment(old)
+            | Self::TooDeep(old) => *old = span,
+            _ => (),
+        }
+        self
+    }
+    /// renders the error together with the line of `expression` it occured in
+    /// and a caret underline below the offending span
+    ///
+    /// ```text
+    /// error: unexpected token
+    ///  | 2 + : 3
+    ///  |     ^
+    /// ```
+    pub fn render(&self, expression: &str) -> String {
+        let mut rendered = format!("error: {}", self);
+        if let Some(span) = self.span() {
+            let start = span.start.min(expression.len());
+            let end = span.end.clamp(start, expression.len());
+            let line_start = expression[..start].rfind('\n').map_or(0, |i| i + 1);
+            let line_end = expression[start..]
+                .find('\n')
+                .map_or(expression.len(), |i| start + i);
+            let line = &expression[line_start..line_end];
+            let offset = expression[line_start..start].chars().count();
+            let width = expression[start..end.min(line_end)].chars().count().max(1);
+            rendered.push_str(&format!(
+                "\n | {}\n | {}{}",
+                line,
+                " ".repeat(offset),
+                "^".repeat(width)
+            ));
+        }
+        rendered
     }
 }
-impl From<ParseBoolError> for Error {
-    fn from(_: ParseBoolError) -> Self {
-        Self::InvalidToken
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnkownCharacter(chr, _) => write!(f, "unknown character `{}`", chr),
+            Self::UnexpectedToken(_) => write!(f, "unexpected token"),
+            Self::NoIdentifierMatch(_) => write!(f, "no function with this name"),
+            Self::InvalidToken(_) => write!(f, "invalid token"),
+            Self::InvalidNamespace(_) => write!(f, "invalid namespace"),
+            Self::UnmatchedBracket(_) => write!(f, "unmatched bracket"),
+            Self::UnexpectedEnd(_) => write!(f, "unexpected end of expression"),
+            Self::InvalidArgCount(_) => write!(f, "wrong number of arguments"),
+            Self::RecursiveDefinition(_) => write!(f, "a function can't call itself"),
+            Self::Redeclaration(_) => write!(f, "variable is already used before its `let`"),
+            Self::ReadBeforeAssignment(_) => write!(f, "variable is read before it is assigned"),
+            Self::TooDeep(_) => write!(f, "expression is nested too deeply"),
+            Self::InvalidArgs => write!(f, "invalid arguments"),
+            Self::InvalidVariable => write!(f, "invalid variable"),
+            Self::NotCompiled => write!(f, "expression is not compiled"),
+            Self::AlreadyCompiled => write!(f, "expression is already compiled"),
+            Self::InvalidIndex => write!(f, "invalid index"),
+            Self::EmptyExpression => write!(f, "empty expression"),
+            Self::LengthMismatch => write!(f, "columns have different lengths"),
+            Self::ResultCount => write!(f, "wrong number of results"),
+        }
     }
 }
 
+impl std::error::Error for Error {}
+
 impl From<TryFromSliceError> for Error {
     fn from(_: TryFromSliceError) -> Self {
         Self::InvalidArgs
     }
 }
+
+// lets `args.try_into()?` pass the arguments of variadic functions on as a slice
+impl From<Infallible> for Error {
+    fn from(infallible: Infallible) -> Self {
+        match infallible {}
+    }
+}
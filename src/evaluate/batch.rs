@@ -0,0 +1,299 @@
+use std::{
+    array,
+    fmt::Debug,
+    panic,
+    simd::{self, LaneCount, Mask, SupportedLaneCount},
+    slice, thread,
+};
+
+use crate::error::Error;
+
+use super::{
+    expression::Expression,
+    function::Function,
+    program::{Frame, Program},
+    value::{simd::Simd, Value},
+};
+
+/// a column of input values for `Expression::eval_batch`, one value per row
+#[derive(Debug, Clone, Copy)]
+pub enum Column<'a> {
+    Float(&'a [f64]),
+    Int(&'a [i64]),
+    Bool(&'a [bool]),
+}
+
+/// a column the results of `Expression::eval_batch` are written to,
+/// results are converted to the type of the column
+#[derive(Debug)]
+pub enum ColumnMut<'a> {
+    Float(&'a mut [f64]),
+    Int(&'a mut [i64]),
+    Bool(&'a mut [bool]),
+}
+
+impl<'a> Column<'a> {
+    pub fn len(&self) -> usize {
+        match self {
+            Column::Float(v) => v.len(),
+            Column::Int(v) => v.len(),
+            Column::Bool(v) => v.len(),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// the rows `start..end` of the column
+    pub fn slice(&self, start: usize, end: usize) -> Column<'a> {
+        match self {
+            Column::Float(v) => Column::Float(&v[start..end]),
+            Column::Int(v) => Column::Int(&v[start..end]),
+            Column::Bool(v) => Column::Bool(&v[start..end]),
+        }
+    }
+    /// loads the rows `start..start + LANES` into a vector, the lanes past the end
+    /// of the column repeat the last row
+    fn chunk<const LANES: usize>(&self, start: usize) -> Value<LANES>
+    where
+        LaneCount<LANES>: SupportedLaneCount,
+    {
+        let row = |lane: usize| (start + lane).min(self.len() - 1);
+        Value::Simd(match self {
+            Column::Float(v) => Simd::Float(simd::Simd::from_array(array::from_fn(|lane| {
+                v[row(lane)]
+            }))),
+            Column::Int(v) => Simd::Int(simd::Simd::from_array(array::from_fn(|lane| {
+                v[row(lane)]
+            }))),
+            Column::Bool(v) => Simd::Bool(Mask::from_array(array::from_fn(|lane| v[row(lane)]))),
+        })
+    }
+}
+
+impl<'a> ColumnMut<'a> {
+    pub fn len(&self) -> usize {
+        match self {
+            ColumnMut::Float(v) => v.len(),
+            ColumnMut::Int(v) => v.len(),
+            ColumnMut::Bool(v) => v.len(),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// a `ColumnMut` borrowing the same rows for a shorter time
+    fn reborrow(&mut self) -> ColumnMut<'_> {
+        match self {
+            ColumnMut::Float(v) => ColumnMut::Float(v),
+            ColumnMut::Int(v) => ColumnMut::Int(v),
+            ColumnMut::Bool(v) => ColumnMut::Bool(v),
+        }
+    }
+    /// splits the column into the rows before `mid` and the rows starting at `mid`
+    pub fn split_at_mut(self, mid: usize) -> (ColumnMut<'a>, ColumnMut<'a>) {
+        match self {
+            ColumnMut::Float(v) => {
+                let (head, tail) = v.split_at_mut(mid);
+                (ColumnMut::Float(head), ColumnMut::Float(tail))
+            }
+            ColumnMut::Int(v) => {
+                let (head, tail) = v.split_at_mut(mid);
+                (ColumnMut::Int(head), ColumnMut::Int(tail))
+            }
+            ColumnMut::Bool(v) => {
+                let (head, tail) = v.split_at_mut(mid);
+                (ColumnMut::Bool(head), ColumnMut::Bool(tail))
+            }
+        }
+    }
+    /// stores `value` into the rows `start..start + LANES` that are in the column,
+    /// a `Single` is written to every row
+    fn set_chunk<const LANES: usize>(&mut self, start: usize, value: Value<LANES>)
+    where
+        LaneCount<LANES>: SupportedLaneCount,
+    {
+        let end = (start + LANES).min(self.len());
+        let lanes = end - start;
+        match (self, value) {
+            (ColumnMut::Float(v), Value::Simd(value)) => {
+                v[start..end].copy_from_slice(&value.as_float().to_array()[..lanes])
+            }
+            (ColumnMut::Int(v), Value::Simd(value)) => {
+                v[start..end].copy_from_slice(&value.as_int().to_array()[..lanes])
+            }
+            (ColumnMut::Bool(v), Value::Simd(value)) => {
+                v[start..end].copy_from_slice(&value.as_mask().to_array()[..lanes])
+            }
+            (ColumnMut::Float(v), Value::Single(value)) => v[start..end].fill(value.as_float()),
+            (ColumnMut::Int(v), Value::Single(value)) => v[start..end].fill(value.as_int()),
+            (ColumnMut::Bool(v), Value::Single(value)) => v[start..end].fill(value.as_bool()),
+        }
+    }
+}
+
+impl<T, const LANES: usize> Program<T, LANES>
+where
+    T: Function<T, LANES> + Clone + Debug,
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    /// evaluates the program once per row of `inputs` and writes the results into `output`
+    ///
+    /// `inputs` holds one column per input variable in the order of the variable indices,
+    /// variables assigned by a script aren't inputs.
+    /// rows are evaluated `LANES` at a time, the rows that don't fill a whole vector
+    /// at the end are padded with copies of the last row, so every row is evaluated
+    /// as a lane of a `Simd` value and gives the same result wherever it is.
+    /// the variable values of `frame` are overwritten.
+    pub fn eval_batch(
+        &self,
+        frame: &mut Frame<LANES>,
+        inputs: &[Column],
+        mut output: ColumnMut,
+    ) -> Result<(), Error> {
+        self.eval_batch_tuple(frame, inputs, slice::from_mut(&mut output))
+    }
+    /// like `eval_batch` for a program returning a tuple, with one column in `outputs` per item
+    pub fn eval_batch_tuple(
+        &self,
+        frame: &mut Frame<LANES>,
+        inputs: &[Column],
+        outputs: &mut [ColumnMut],
+    ) -> Result<(), Error> {
+        self.check_columns(inputs, outputs)?;
+        let rows = outputs[0].len();
+        for start in (0..rows).step_by(LANES) {
+            for (index, input) in inputs.iter().enumerate() {
+                frame.set_variable_by_index(index, input.chunk(start))?;
+            }
+            self.run(frame)?;
+            for (output, value) in outputs.iter_mut().zip(frame.results()) {
+                output.set_chunk(start, *value);
+            }
+        }
+        Ok(())
+    }
+    /// like `eval_batch` but splits the rows into one part per thread,
+    /// every thread evaluates its part with its own `Frame` and writes a disjoint part of `output`
+    ///
+    /// a `threads` count of 0 uses `std::thread::available_parallelism`.
+    /// a function panicking in a worker panics in the caller with the same payload
+    pub fn eval_batch_parallel(
+        &self,
+        inputs: &[Column],
+        mut output: ColumnMut,
+        threads: usize,
+    ) -> Result<(), Error>
+    where
+        T: Send + Sync,
+    {
+        self.eval_batch_tuple_parallel(inputs, slice::from_mut(&mut output), threads)
+    }
+    /// like `eval_batch_parallel` for a program returning a tuple,
+    /// with one column in `outputs` per item
+    pub fn eval_batch_tuple_parallel(
+        &self,
+        inputs: &[Column],
+        outputs: &mut [ColumnMut],
+        threads: usize,
+    ) -> Result<(), Error>
+    where
+        T: Send + Sync,
+    {
+        self.check_columns(inputs, outputs)?;
+        let rows = outputs[0].len();
+        let threads = match threads {
+            0 => thread::available_parallelism().map_or(1, |threads| threads.get()),
+            threads => threads,
+        };
+        // every part except the last one is a multiple of `LANES` long
+        // so only the last part has rows that don't fill a vector
+        let part = (rows / threads).next_multiple_of(LANES).max(LANES);
+        thread::scope(|scope| {
+            let mut workers = Vec::with_capacity(threads);
+            let mut outputs: Vec<ColumnMut> = outputs.iter_mut().map(ColumnMut::reborrow).collect();
+            let mut start = 0;
+            while start < rows {
+                let end = start + part.min(rows - start);
+                let (mut heads, tails): (Vec<ColumnMut>, Vec<ColumnMut>) = outputs
+                    .into_iter()
+                    .map(|output| output.split_at_mut(end - start))
+                    .unzip();
+                let inputs: Vec<Column> = inputs.iter().map(|input| input.slice(start, end)).collect();
+                workers.push(scope.spawn(move || {
+                    self.eval_batch_tuple(&mut self.frame(), &inputs, &mut heads)
+                }));
+                outputs = tails;
+                start = end;
+            }
+            // a panic in a worker is passed on with its original payload
+            workers
+                .into_iter()
+                .try_for_each(|worker| {
+                    worker.join().unwrap_or_else(|payload| panic::resume_unwind(payload))
+                })
+        })
+    }
+    /// checks that there is one column per input and per result
+    /// and that every column has the same length
+    fn check_columns(&self, inputs: &[Column], outputs: &[ColumnMut]) -> Result<(), Error> {
+        if inputs.len() != self.input_count() {
+            return Err(Error::InvalidArgs);
+        }
+        if outputs.len() != self.result_count() {
+            return Err(Error::ResultCount);
+        }
+        let rows = outputs[0].len();
+        if inputs.iter().any(|input| input.len() != rows)
+            || outputs.iter().any(|output| output.len() != rows)
+        {
+            return Err(Error::LengthMismatch);
+        }
+        Ok(())
+    }
+}
+
+impl<T, const LANES: usize> Expression<T, LANES>
+where
+    T: Function<T, LANES> + Clone + Debug,
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    /// evaluates the expression once per row of `inputs` and writes the results into `output`,
+    /// see `Program::eval_batch`
+    ///
+    /// unlike `set_variable` followed by `eval` this leaves the variable values unchanged
+    pub fn eval_batch(&self, inputs: &[Column], output: ColumnMut) -> Result<(), Error> {
+        let program = self.program()?;
+        program.eval_batch(&mut program.frame(), inputs, output)
+    }
+    /// evaluates the expression on multiple threads, see `Program::eval_batch_parallel`
+    pub fn eval_batch_parallel(
+        &self,
+        inputs: &[Column],
+        output: ColumnMut,
+        threads: usize,
+    ) -> Result<(), Error>
+    where
+        T: Send + Sync,
+    {
+        self.program()?.eval_batch_parallel(inputs, output, threads)
+    }
+    /// evaluates an expression returning a tuple with one column in `outputs` per item,
+    /// see `Program::eval_batch_tuple`
+    pub fn eval_batch_tuple(&self, inputs: &[Column], outputs: &mut [ColumnMut]) -> Result<(), Error> {
+        let program = self.program()?;
+        program.eval_batch_tuple(&mut program.frame(), inputs, outputs)
+    }
+    /// evaluates an expression returning a tuple on multiple threads,
+    /// see `Program::eval_batch_tuple_parallel`
+    pub fn eval_batch_tuple_parallel(
+        &self,
+        inputs: &[Column],
+        outputs: &mut [ColumnMut],
+        threads: usize,
+    ) -> Result<(), Error>
+    where
+        T: Send + Sync,
+    {
+        self.program()?.eval_batch_tuple_parallel(inputs, outputs, threads)
+    }
+}
@@ -0,0 +1,22 @@
+use std::simd::{LaneCount, SupportedLaneCount};
+
+use super::{function::Function, parse_element::ParseElement};
+
+/// a function defined in the expression language with `Expression::define`
+///
+/// the body is kept as the parsed tree, the parameters are its first `params` variables
+/// and every call is replaced by a copy of the tree with the arguments in place of them
+#[derive(Debug, Clone)]
+pub(crate) struct Definition<T, const LANES: usize>
+where
+    T: Function<T, LANES>,
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    pub(crate) name: String,
+    pub(crate) params: usize,
+    pub(crate) elements: Vec<ParseElement<T, LANES>>,
+    pub(crate) root: usize,
+    /// the names of the variables of the body by index, the ones after the parameters
+    /// are the variables of the expression calling the function
+    pub(crate) variables: Vec<String>,
+}
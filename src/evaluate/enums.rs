@@ -1,5 +1,5 @@
 use std::{
-    ops::{Add, Div, Mul, Rem, Sub},
+    ops::{Add, Div, Mul, Neg, Rem, Sub},
     simd::{LaneCount, SupportedLaneCount},
 };
 
@@ -13,7 +13,7 @@ pub(crate) enum TokenKind {
     Operator(Operator),
     Special(Special),
 }
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub(crate) enum Literal {
     Int,
     Float,
@@ -29,7 +29,7 @@ pub(crate) enum Identifier {
     Function,
     Variable,
 }
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub(crate) enum Operator {
     Add,
     Sub,
@@ -37,6 +37,9 @@ pub(crate) enum Operator {
     Div,
     Mod,
     Pow,
+    Neg,
+    /// the square root, only created by the optimizer from `x^0.5`
+    Sqrt,
 
     Not,
     Equal,
@@ -52,38 +55,53 @@ pub(crate) enum Operator {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum Special {
     Namespace,
-    NegZero,
     Comma,
+    Question,
+    Colon,
+    Semicolon,
+    /// the `let` keyword, only recognized in front of a variable
+    Let,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Associativity {
+    Left,
+    Right,
 }
 
-impl Bracket {
-    pub fn weight(&self) -> i16 {
+impl Operator {
+    /// the precedence unary operators parse their operand with,
+    /// only `^` binds tighter so `-a^2` is `-(a^2)`
+    pub(crate) const UNARY_PRECEDENCE: u8 = 8;
+    /// the binding power of the operator when used as a binary operator,
+    /// `None` for operators that can only be used as unary operators
+    pub(crate) fn precedence(&self) -> Option<u8> {
+        Some(match self {
+            Self::Or => 1,
+            Self::Xor => 2,
+            Self::And => 3,
+            Self::Equal | Self::NotEqual => 4,
+            Self::Greater | Self::GreaterEqual | Self::Smaller | Self::SmallerEqual => 5,
+            Self::Add | Self::Sub => 6,
+            Self::Mul | Self::Div | Self::Mod => 7,
+            Self::Pow => 9,
+            Self::Neg | Self::Not | Self::Sqrt => return None,
+        })
+    }
+    pub(crate) fn associativity(&self) -> Associativity {
         match self {
-            Self::Opened => 100,
-            Self::Closed => -100,
+            Self::Pow => Associativity::Right,
+            _ => Associativity::Left,
         }
     }
-}
-impl Operator {
-    pub(crate) fn weight(&self) -> i16 {
+    pub(crate) fn eval_unary<const LANES: usize>(&self, operand: Value<LANES>) -> Value<LANES>
+    where
+        LaneCount<LANES>: SupportedLaneCount,
+    {
         match self {
-            Self::Add => 20,
-            Self::Sub => 20,
-            Self::Mul => 21,
-            Self::Div => 21,
-            Self::Mod => 22,
-            Self::Pow => 22,
-
-            Self::Not => 1,
-            Self::Equal => 2,
-            Self::And => 3,
-            Self::Or => 4,
-            Self::Xor => 5,
-            Self::NotEqual => 7,
-            Self::Greater => 8,
-            Self::GreaterEqual => 9,
-            Self::Smaller => 10,
-            Self::SmallerEqual => 11,
+            Self::Neg => -operand,
+            Self::Not => operand.not(),
+            Self::Sqrt => operand.sqrt(),
+            _ => unreachable!(),
         }
     }
     pub(crate) fn eval<const LANES: usize>(
@@ -101,19 +119,16 @@ impl Operator {
             Self::Div => lhs / rhs,
             Self::Mod => lhs % rhs,
             Self::Pow => lhs.pow(rhs),
-            _ => todo!(),
-            /*
-            Self::Equal => Single::Bool(lhs == rhs),
-            Self::NotEqual => Single::Bool(lhs != rhs),
-            Self::Greater => Single::Bool(lhs > rhs),
-            Self::GreaterEqual => Single::Bool(lhs >= rhs),
-            Self::Smaller => Single::Bool(lhs < rhs),
-            Self::SmallerEqual => Single::Bool(lhs <= rhs),
+            Self::Equal => lhs.equal(rhs),
+            Self::NotEqual => lhs.not_equal(rhs),
+            Self::Greater => lhs.greater(rhs),
+            Self::GreaterEqual => lhs.greater_equal(rhs),
+            Self::Smaller => lhs.smaller(rhs),
+            Self::SmallerEqual => lhs.smaller_equal(rhs),
             Self::And => lhs.and(rhs),
             Self::Or => lhs.or(rhs),
             Self::Xor => lhs.xor(rhs),
-            _ => unreachable!(),
-            */
+            Self::Neg | Self::Not | Self::Sqrt => unreachable!(),
         }
     }
 }
@@ -1,12 +1,18 @@
-use crate::{error::Error, stack::Stack};
+use crate::{
+    error::{Error, Span},
+    stack::Stack,
+};
 
 use super::{
-    enums::{Bracket, Identifier, Literal, Operator, Special, TokenKind},
+    definition::Definition,
+    enums::{Associativity, Bracket, Identifier, Literal, Operator, Special, TokenKind},
     function::Function,
+    instruction::Instruction,
     node::Node,
     parse_element::ParseElement,
+    program::{Frame, Program},
     token::Token,
-    value::{single::Single, Value},
+    value::{simd::Simd, single::Single, Value},
     variables::Variables,
 };
 use core::fmt;
@@ -15,15 +21,22 @@ use std::{
     collections::HashMap,
     fmt::Debug,
     simd::{LaneCount, SupportedLaneCount},
+    sync::{Mutex, PoisonError, TryLockError},
 };
 
-struct IndexedWeight {
-    weight: i16,
-    index: usize,
-}
-
 /// An `Expression` contains the expression string and the compiled version of that expression.
 ///
+/// compiling parses the string into a tree of nodes which is then flattened
+/// into a tape of instructions that `eval` runs without recursion
+///
+/// the string can also be a script of statements separated by `;` like `let t = a * 2; out = t + 1; t ^ 2`.
+/// `let` names an intermediate, `name = value` assigns a variable that can be read with `variable`
+/// after `eval_assign`, and the value of the last statement is the result.
+/// a single `=` after the name at the start of a statement of a script is an assignment,
+/// otherwise it compares like `==`, and `out=!b` there assigns `!b`. an expression with a `;` is a script.
+/// an expression or the last statement of a script can be a tuple like `(a + b, a - b)`
+/// that is evaluated with `eval_tuple` into one value per item
+///
 /// # UB
 /// Compiling the same expression multiple times is UB
 #[derive(Debug)]
@@ -31,19 +44,132 @@ pub struct Expression<T, const LANES: usize>
 where
     T: Function<T, LANES>,
     LaneCount<LANES>: SupportedLaneCount,
-    [(); T::MAX_ARGS]:,
 {
     elements: Vec<ParseElement<T, LANES>>,
+    spans: Vec<Span>,
     variables: Variables<LANES>,
     expression: String,
     top_node: Option<usize>,
+    instructions: Vec<Instruction<T, LANES>>,
+    stack_size: usize,
+    context: T::Context,
+    definitions: Vec<Definition<T, LANES>>,
+    /// the number of evaluations of shared subexpressions the tape skips
+    eliminated: usize,
+    /// the number of `parse_expression` calls being parsed, limited to `MAX_DEPTH`
+    depth: usize,
+    /// the scratch space of `eval`, sized by `emit` and reused so evaluating doesn't allocate
+    frame: Mutex<Frame<LANES>>,
+}
+
+/// the state of `emit` for evaluating subexpressions that are used more than once only once
+struct Shared {
+    /// the number of parents of every node
+    uses: Vec<usize>,
+    /// the temporary holding the value of a node and the branches it was stored in
+    stored: HashMap<usize, (usize, Vec<usize>)>,
+    /// the conditional branches and right sides of `&&` and `||` being emitted
+    branches: Vec<usize>,
+    next_branch: usize,
+    eliminated: usize,
+}
+
+/// the parts of a node that identify it when shared by `share_subexpressions`,
+/// except for the function of a call which is compared with `Function::is_same`
+#[derive(PartialEq, Eq, Hash)]
+enum ShareKey<const LANES: usize> {
+    /// the type and the bits of the value
+    Single(Literal, u64),
+    Simd(Literal, [u64; LANES]),
+    Variable(usize),
+    Unary(Operator, usize),
+    Instruction(Operator, usize, usize),
+    MulAdd(usize, usize, usize),
+    Conditional(usize, usize, usize),
+    Function(Vec<usize>),
+}
+
+impl<const LANES: usize> ShareKey<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    /// the key of `node`, `None` for the nodes that are never shared
+    fn of<T: Function<T, LANES>>(node: &Node<T, LANES>) -> Option<Self> {
+        Some(match node {
+            Node::Literal(Value::Single(value)) => match *value {
+                Single::Int(value) => Self::Single(Literal::Int, value as u64),
+                Single::Float(value) => Self::Single(Literal::Float, value.to_bits()),
+                Single::Bool(value) => Self::Single(Literal::Bool, value as u64),
+            },
+            Node::Literal(Value::Simd(value)) => match value {
+                Simd::Int(value) => Self::Simd(Literal::Int, value.to_array().map(|v| v as u64)),
+                Simd::Float(value) => Self::Simd(Literal::Float, value.to_array().map(f64::to_bits)),
+                Simd::Bool(value) => Self::Simd(Literal::Bool, value.to_array().map(u64::from)),
+            },
+            Node::Variable { index } => Self::Variable(*index),
+            Node::Unary { operator, operand } => Self::Unary(*operator, *operand),
+            Node::Instruction { operator, lhs, rhs } => Self::Instruction(*operator, *lhs, *rhs),
+            Node::MulAdd { lhs, rhs, addend } => Self::MulAdd(*lhs, *rhs, *addend),
+            Node::Conditional {
+                condition,
+                then,
+                otherwise,
+            } => Self::Conditional(*condition, *then, *otherwise),
+            Node::Function { args, .. } => Self::Function(args.clone()),
+            Node::Call { .. } | Node::Assign { .. } | Node::Sequence { .. } | Node::Tuple { .. } => {
+                return None
+            }
+        })
+    }
+}
+
+/// the state of `optimize_with`, the results are cached per node since nodes can be shared
+struct Optimizer<const LANES: usize>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    level: OptimizationLevel,
+    /// the values of the optimized nodes, `None` for the ones that aren't constant
+    values: HashMap<usize, Option<Value<LANES>>>,
+    kinds: HashMap<usize, Option<Literal>>,
+}
+
+impl Shared {
+    fn enter_branch(&mut self) {
+        self.branches.push(self.next_branch);
+        self.next_branch += 1;
+    }
+    fn leave_branch(&mut self) {
+        self.branches.pop();
+    }
+}
+
+/// the deepest nesting of an expression, the parser and the passes over the parsed tree
+/// recurse once per level so deeper expressions are rejected with `Error::TooDeep`
+/// instead of overflowing the stack
+pub const MAX_DEPTH: usize = 128;
+
+/// the rewrites `Expression::optimize_with` is allowed to apply
+///
+/// `Safe` only applies rewrites that give the same results for ints and floats, `FastMath`
+/// also applies ones that are only exact for real numbers, like `x + 0` which turns `-0.0`
+/// into `0.0`, `x * 0` which is `NaN` for infinite `x`, re-associating float constants,
+/// `x^0.5` to `sqrt(x)` or fusing `a * b + c` into a `mul_add`.
+///
+/// the types of the variables aren't known ahead of time, so at both levels a bool variable
+/// can stay a bool where evaluating `a * 1` or `--a` gives an int. `x * 0` and `x - x`
+/// are only turned into `0` if the type of `x` is known
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptimizationLevel {
+    #[default]
+    Safe,
+    FastMath,
 }
 impl<T, const LANES: usize> fmt::Display for Expression<T, LANES>
 where
     T: Function<T, LANES>,
     T: Clone + Debug,
     LaneCount<LANES>: SupportedLaneCount,
-    [(); T::MAX_ARGS]:,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.expression)
@@ -54,32 +180,134 @@ where
     T: Function<T, LANES>,
     T: Clone + Debug,
     LaneCount<LANES>: SupportedLaneCount,
-    [(); T::MAX_ARGS]:,
 {
     pub(crate) fn to_tokens(&mut self) -> Result<&mut Self, Error> {
         // WHY DO I HAVE TO CLONE NOW!?
         let cloned = self.expression.clone();
-        for (index, &chr) in cloned.as_bytes().iter().enumerate() {
+        for (index, chr) in cloned.char_indices() {
             self.push(chr, index)?;
         }
+        // a namespace has to be followed by a function or another namespace, and inside of
+        // a conditional there has to be a `:` left for every open `?`. otherwise it is a
+        // variable followed by the `:` of the conditional like in `c ? a:b` or `c ? a:sqrt(b)`
+        let script = self
+            .elements
+            .iter()
+            .any(|element| matches!(
+                element,
+                ParseElement::Token(token) if token.kind() == TokenKind::Special(Special::Semicolon)
+            ));
+        let mut open = vec![0];
+        let mut index = 0;
+        while index < self.elements.len() {
+            let next_is_function = matches!(
+                self.elements.get(index + 1),
+                Some(ParseElement::Token(next)) if matches!(
+                    next.kind(),
+                    TokenKind::Identifier(Identifier::Function) | TokenKind::Special(Special::Namespace)
+                )
+            );
+            let next_is_variable = matches!(
+                self.elements.get(index + 1),
+                Some(ParseElement::Token(next))
+                    if next.kind() == TokenKind::Identifier(Identifier::Variable)
+            );
+            // `out=!b` at the start of a statement assigns `!b` where a single `=` assigns
+            let after_name = index > 0
+                && self.peek_kind(index - 1) == Some(TokenKind::Identifier(Identifier::Variable))
+                && match index.checked_sub(2).map(|before| self.peek_kind(before)) {
+                    None | Some(Some(TokenKind::Special(Special::Semicolon))) => script,
+                    Some(kind) => kind == Some(TokenKind::Special(Special::Let)),
+                };
+            let level = open.len() - 1;
+            // only a `::` inside a conditional can be a `:` so the colons are counted lazily
+            let split_namespace = !next_is_function
+                || open[level] > 0
+                    && self.peek_kind(index) == Some(TokenKind::Special(Special::Namespace))
+                    && self.colons_left(index + 1) < open[level];
+            if let ParseElement::Token(token) = &mut self.elements[index] {
+                match token.kind() {
+                    TokenKind::Identifier(Identifier::Variable)
+                        if next_is_variable && token.slice(&self.expression) == "let" =>
+                    {
+                        token.set_kind(TokenKind::Special(Special::Let));
+                    }
+                    TokenKind::Special(Special::Namespace) if split_namespace =>
+                    {
+                        token.set_kind(TokenKind::Identifier(Identifier::Variable));
+                        let colon = Token::new(TokenKind::Special(Special::Colon), token.span().end);
+                        self.elements.insert(index + 1, ParseElement::Token(colon));
+                    }
+                    TokenKind::Operator(Operator::NotEqual)
+                        if after_name && token.slice(&self.expression) == "=!" =>
+                    {
+                        let start = token.span().start;
+                        token.set_kind(TokenKind::Operator(Operator::Equal));
+                        token.set_end(start + 1);
+                        let not = Token::new(TokenKind::Operator(Operator::Not), start + 1);
+                        self.elements.insert(index + 1, ParseElement::Token(not));
+                    }
+                    TokenKind::Special(Special::Question) => open[level] += 1,
+                    TokenKind::Special(Special::Colon) => {
+                        open[level] = open[level].saturating_sub(1)
+                    }
+                    TokenKind::Special(Special::Semicolon) => open[level] = 0,
+                    TokenKind::Bracket(Bracket::Opened) => open.push(0),
+                    TokenKind::Bracket(Bracket::Closed) if open.len() > 1 => {
+                        open.pop();
+                    }
+                    _ => {}
+                }
+            }
+            index += 1;
+        }
         Ok(self)
     }
+    /// the number of `:` from `start` to the end of the enclosing brackets or statement
+    /// that aren't taken by a `?` in between
+    fn colons_left(&self, start: usize) -> usize {
+        let (mut depth, mut colons, mut questions) = (0usize, 0usize, 0);
+        for element in &self.elements[start..] {
+            let ParseElement::Token(token) = element else {
+                continue;
+            };
+            match token.kind() {
+                TokenKind::Bracket(Bracket::Opened) => depth += 1,
+                TokenKind::Bracket(Bracket::Closed) if depth == 0 => break,
+                TokenKind::Bracket(Bracket::Closed) => depth -= 1,
+                TokenKind::Special(Special::Semicolon) if depth == 0 => break,
+                TokenKind::Special(Special::Question) if depth == 0 => questions += 1,
+                TokenKind::Special(Special::Colon) if depth == 0 => colons += 1,
+                _ => {}
+            }
+        }
+        colons.saturating_sub(questions)
+    }
     pub(crate) fn to_nodes<const N: usize>(&mut self) -> Result<&mut Self, Error> {
         {
             let mut namespaces = Stack::<&str, N>::new();
             for element in &mut self.elements {
                 if let ParseElement::Token(token) = element {
+                    self.spans.push(token.span());
+                    if token.kind() == TokenKind::Identifier(Identifier::Variable)
+                        && matches!(token.slice(&self.expression), "true" | "false")
+                    {
+                        token.set_kind(TokenKind::Literal(Literal::Bool));
+                    }
                     match token.kind() {
                         TokenKind::Literal(l) => {
+                            // digit separators carry no meaning
+                            let slice = token.slice(&self.expression).replace('_', "");
+                            let span = token.span();
                             let value = Value::Single(match l {
-                                Literal::Bool => {
-                                    Single::Bool(token.slice(&self.expression).parse::<bool>()?)
-                                }
-                                Literal::Float => {
-                                    Single::Float(token.slice(&self.expression).parse::<f64>()?)
-                                }
+                                Literal::Bool => Single::Bool(
+                                    slice.parse().map_err(|_| Error::InvalidToken(span))?,
+                                ),
+                                Literal::Float => Single::Float(
+                                    slice.parse().map_err(|_| Error::InvalidToken(span))?,
+                                ),
                                 Literal::Int => {
-                                    Single::Int(token.slice(&self.expression).parse::<i64>()?)
+                                    Single::Int(parse_int(&slice).ok_or(Error::InvalidToken(span))?)
                                 }
                             });
                             *element = ParseElement::Node(Node::Literal(value));
@@ -91,26 +319,55 @@ where
                                 rhs: 0,
                             })
                         }
+                        TokenKind::Identifier(Identifier::Function)
+                            if namespaces.is_empty() && token.slice(&self.expression) == "if" =>
+                        {
+                            *element = ParseElement::Node(Node::Conditional {
+                                condition: 0,
+                                then: 0,
+                                otherwise: 0,
+                            })
+                        }
                         TokenKind::Identifier(Identifier::Function) => {
                             let identifier = token.slice(&self.expression);
-                            let mut namespaces = namespaces.iter();
-                            let function = <T as Function<T, LANES>>::from_string(
-                                &mut namespaces,
-                                identifier,
-                            )?;
-                            *element = ParseElement::Node(Node::Function {
-                                function,
-                                args: Stack::new(),
-                            })
+                            // defined functions shadow the ones of `T`
+                            let definition = self
+                                .definitions
+                                .iter()
+                                .position(|definition| definition.name == identifier);
+                            *element = match definition {
+                                Some(definition) if namespaces.is_empty() => {
+                                    ParseElement::Node(Node::Call {
+                                        definition,
+                                        args: Vec::new(),
+                                    })
+                                }
+                                _ => {
+                                    let function = <T as Function<T, LANES>>::resolve(
+                                        &self.context,
+                                        &mut namespaces.iter(),
+                                        identifier,
+                                    )
+                                    .map_err(|e| e.with_span(token.span()))?;
+                                    namespaces.clear();
+                                    ParseElement::Node(Node::Function {
+                                        function,
+                                        args: Vec::new(),
+                                    })
+                                }
+                            }
                         }
-                        TokenKind::Bracket(_) | TokenKind::Special(Special::Comma) => (),
+                        TokenKind::Bracket(_)
+                        | TokenKind::Special(
+                            Special::Comma
+                            | Special::Question
+                            | Special::Colon
+                            | Special::Semicolon
+                            | Special::Let,
+                        ) => {}
                         TokenKind::Special(Special::Namespace) => {
                             namespaces.push(token.slice(&self.expression));
                         }
-                        TokenKind::Special(Special::NegZero) => {
-                            *element =
-                                ParseElement::Node(Node::Literal(Value::Single(Single::Int(0))));
-                        }
                         TokenKind::Identifier(Identifier::Variable) => {
                             let identifier = token.slice(&self.expression);
                             let index = self.variables.find_or_set(identifier);
@@ -122,183 +379,706 @@ where
         }
         Ok(self)
     }
-    pub(crate) fn set_indices(&'b mut self) -> Result<&mut Self, Error> {
-        struct NodeInfo<'b, T, const LANES: usize>
-        where
-            T: Function<T, LANES>,
-            LaneCount<LANES>: SupportedLaneCount,
-            [(); T::MAX_ARGS]:,
-        {
-            index: usize,
-            node: &'b mut Node<T, LANES>,
-            weight: i16,
-        }
-        let mut bracket_weight = 0;
-        let mut lowest_weight = IndexedWeight {
-            weight: i16::MAX,
-            index: 0,
+    /// links the nodes into a tree using precedence climbing and sets `top_node` to its root
+    ///
+    /// the statements of a script become a `Sequence` in place of the first `;`
+    pub(crate) fn parse(&mut self) -> Result<&mut Self, Error> {
+        // outside of a script `a = 1` compares like it always did
+        let script = self.elements.iter().any(|element| {
+            matches!(
+                element,
+                ParseElement::Token(token)
+                    if token.kind() == TokenKind::Special(Special::Semicolon)
+            )
+        });
+        self.depth = 0;
+        let mut cursor = 0;
+        let mut statements = vec![self.parse_statement(&mut cursor, script)?];
+        let mut sequence = None;
+        while self.peek_kind(cursor) == Some(TokenKind::Special(Special::Semicolon)) {
+            sequence.get_or_insert(cursor);
+            cursor += 1;
+            // a trailing `;` is allowed
+            if cursor == self.elements.len() {
+                break;
+            }
+            statements.push(self.parse_statement(&mut cursor, script)?);
+        }
+        if cursor < self.elements.len() {
+            return Err(Error::UnexpectedToken(self.spans[cursor]));
+        }
+        for statement in &statements {
+            self.check_depth(*statement)?;
+        }
+        // reading an assigned variable before its first assignment would give the value
+        // left over from the previous evaluation
+        let mut unassigned = vec![false; self.variables.values().len()];
+        for statement in &statements {
+            if let ParseElement::Node(Node::Assign { index, .. }) = self.elements[*statement] {
+                unassigned[index] = true;
+            }
+        }
+        for statement in &statements {
+            let (target, value) = match self.elements[*statement] {
+                ParseElement::Node(Node::Assign { index, value }) => (Some(index), value),
+                _ => (None, *statement),
+            };
+            if let Some(span) = self.find_read(value, &unassigned) {
+                return Err(Error::ReadBeforeAssignment(span));
+            }
+            if let Some(target) = target {
+                unassigned[target] = false;
+            }
+        }
+        let top_node = match sequence {
+            Some(index) if statements.len() > 1 => {
+                self.elements[index] = ParseElement::Node(Node::Sequence { statements });
+                index
+            }
+            _ => statements[0],
         };
-        let mut iter = self
-            .elements
-            .iter_mut()
-            .enumerate()
-            .filter_map(|(index, e)| match e {
-                ParseElement::<T, LANES>::Node(node) => match node {
-                    <Node<T, LANES>>::Instruction {
-                        operator: o,
-                        lhs,
-                        rhs,
-                    } => {
-                        *lhs = index - 1;
-                        *rhs = index + 1;
-                        let weight = o.weight();
-                        let info = NodeInfo::<'b, T, LANES> {
-                            index,
-                            node,
-                            weight: weight + bracket_weight,
-                        };
-                        Some(info)
-                    }
-                    <Node<T, LANES>>::Function { function, args } => {
-                        let info = NodeInfo::<'b, T, LANES> {
-                            index,
-                            node,
-                            weight: bracket_weight,
-                        };
-                        Some(info)
-                    }
-                    _ => None,
-                },
-                ParseElement::Token(t) => match t.kind() {
-                    TokenKind::Bracket(b) => {
-                        bracket_weight += b.weight();
-                        None
-                    }
-                    _ => None,
-                },
-            })
-            .peekable();
+        self.top_node = Some(top_node);
+        let result = self.result_node();
+        let tuple = self.elements.iter().enumerate().position(|(index, element)| {
+            matches!(element, ParseElement::Node(Node::Tuple { .. })) && Some(index) != result
+        });
+        if let Some(index) = tuple {
+            return Err(Error::UnexpectedToken(self.spans[index]));
+        }
+        Ok(self)
+    }
+    /// the span of the first variable marked in `variables` that is read by the subtree at `index`
+    fn find_read(&self, index: usize, variables: &[bool]) -> Option<Span> {
+        match &self.elements[index] {
+            ParseElement::Node(Node::Variable { index: variable }) if variables[*variable] => {
+                Some(self.spans[index])
+            }
+            ParseElement::Node(node) => node
+                .children()
+                .into_iter()
+                .find_map(|child| self.find_read(child, variables)),
+            ParseElement::Token(_) => None,
+        }
+    }
+    /// the node the expression evaluates to, the last statement of a script
+    fn result_node(&self) -> Option<usize> {
+        match &self.elements[self.top_node?] {
+            ParseElement::Node(Node::Sequence { statements }) => statements.last().copied(),
+            _ => self.top_node,
+        }
+    }
+    /// the number of values the expression evaluates to, more than one for a tuple
+    pub fn result_count(&self) -> usize {
+        match self.result_node().map(|index| &self.elements[index]) {
+            Some(ParseElement::Node(Node::Tuple { items })) => items.len(),
+            _ => 1,
+        }
+    }
+    /// parses `let name = value`, `name = value` if the statement is part of a `script`
+    /// or an expression and returns the index of the resulting subtree
+    fn parse_statement(&mut self, cursor: &mut usize, script: bool) -> Result<usize, Error> {
+        let is_let = self.peek_kind(*cursor) == Some(TokenKind::Special(Special::Let));
+        let name = *cursor + is_let as usize;
+        let assign = name + 1;
+        let target = match (self.elements.get(name), self.elements.get(assign)) {
+            (
+                Some(ParseElement::Node(Node::Variable { index })),
+                Some(ParseElement::Node(Node::Instruction {
+                    operator: Operator::Equal,
+                    ..
+                })),
+            ) if (script || is_let) && self.spans[assign].end - self.spans[assign].start == 1 => {
+                *index
+            }
+            _ if is_let => {
+                return Err(match self.elements.get(assign) {
+                    Some(_) => Error::UnexpectedToken(self.spans[assign]),
+                    None => Error::UnexpectedEnd(self.end_span()),
+                })
+            }
+            _ => return self.parse_expression(cursor, 0),
+        };
+        // the variable of a `let` can't have been used by an earlier statement
+        let used = self.elements[..name].iter().any(|element| {
+            matches!(element, ParseElement::Node(Node::Variable { index }) if *index == target)
+        });
+        if is_let && used {
+            return Err(Error::Redeclaration(self.spans[name]));
+        }
+        *cursor = assign + 1;
+        let value = self.parse_expression(cursor, 0)?;
+        self.elements[assign] = ParseElement::Node(Node::Assign {
+            index: target,
+            value,
+        });
+        Ok(assign)
+    }
+    /// parses operators binding at least as tight as `min_precedence` and returns the index of the
+    /// resulting subtree, leaving `cursor` on the first element that isn't part of it
+    ///
+    /// the conditional `?:` binds the loosest and is only parsed for a `min_precedence` of 0
+    fn parse_expression(&mut self, cursor: &mut usize, min_precedence: u8) -> Result<usize, Error> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            let span = self.spans.get(*cursor).copied().unwrap_or(self.end_span());
+            return Err(Error::TooDeep(span));
+        }
+        let mut lhs = self.parse_prefix(cursor)?;
         loop {
-            let c = iter.next();
-            let n = iter.peek_mut();
-            match (c, n) {
-                (Some(curr), Some(next)) => {
-                    let ord = curr.weight.cmp(&next.weight);
-
-                    match ord {
-                        Ordering::Equal => {
-                            if let Node::Instruction { lhs, .. } = next.node {
-                                *lhs = curr.index;
-                            } else {
-                            }
-                            if lowest_weight.weight >= next.weight {
-                                lowest_weight.weight = next.weight;
-                                lowest_weight.index = next.index;
-                            }
-                        }
-                        Ordering::Greater => {
-                            if let Node::Instruction { lhs, .. } = next.node {
-                                *lhs = curr.index;
-                            } else {
-                            }
-                            if lowest_weight.weight >= next.weight {
-                                lowest_weight.weight = next.weight;
-                                lowest_weight.index = next.index;
-                            }
-                        }
-                        Ordering::Less => {
-                            if let Node::Instruction { rhs, .. } = curr.node {
-                                *rhs = next.index;
-                            } else {
-                            }
-                            if lowest_weight.weight >= curr.weight {
-                                lowest_weight.weight = curr.weight;
-                                lowest_weight.index = curr.index;
-                            }
-                        }
+            let index = *cursor;
+            match self.elements.get(index) {
+                Some(ParseElement::Node(Node::Instruction { operator, .. })) => {
+                    let precedence = operator
+                        .precedence()
+                        .ok_or(Error::UnexpectedToken(self.spans[index]))?;
+                    if precedence < min_precedence {
+                        break;
                     }
-                }
-                (Some(curr), None) => {
-                    if lowest_weight.weight >= curr.weight {
-                        lowest_weight.weight = curr.weight;
-                        lowest_weight.index = curr.index
+                    let next_precedence = match operator.associativity() {
+                        Associativity::Left => precedence + 1,
+                        Associativity::Right => precedence,
+                    };
+                    *cursor += 1;
+                    let rhs = self.parse_expression(cursor, next_precedence)?;
+                    if let ParseElement::Node(node) = &mut self.elements[index] {
+                        let (l, r) = node.as_mut_instruction_indices();
+                        *l = lhs;
+                        *r = rhs;
                     }
                 }
+                Some(ParseElement::Token(token))
+                    if token.kind() == TokenKind::Special(Special::Question)
+                        && min_precedence == 0 =>
+                {
+                    *cursor += 1;
+                    let then = self.parse_expression(cursor, 0)?;
+                    self.expect(cursor, TokenKind::Special(Special::Colon))?;
+                    // right associative, `a ? b : c ? d : e` is `a ? b : (c ? d : e)`
+                    let otherwise = self.parse_expression(cursor, 0)?;
+                    self.elements[index] = ParseElement::Node(Node::Conditional {
+                        condition: lhs,
+                        then,
+                        otherwise,
+                    });
+                }
                 _ => break,
             }
+            lhs = index;
         }
-        self.top_node = Some(lowest_weight.index);
-        Ok(self)
+        self.depth -= 1;
+        Ok(lhs)
     }
-    pub(crate) fn set_function_args(&mut self) -> Result<(), Error> {
-        let mut index = 0;
-        while index < self.elements.len() {
-            let element = &self.elements[index];
-            // skip until element is a function
-            if let ParseElement::Node(Node::Function { .. }) = element {
-                let mut lowest_weight = IndexedWeight {
-                    weight: i16::MAX,
-                    index: 0,
+    /// parses a single operand: a literal, variable, function call, bracketed expression
+    /// or an operand preceded by a unary operator
+    fn parse_prefix(&mut self, cursor: &mut usize) -> Result<usize, Error> {
+        // namespaces are already part of the function node
+        while let Some(ParseElement::Token(token)) = self.elements.get(*cursor) {
+            if token.kind() == TokenKind::Special(Special::Namespace) {
+                *cursor += 1;
+            } else {
+                break;
+            }
+        }
+        let index = *cursor;
+        let element = self
+            .elements
+            .get(index)
+            .ok_or_else(|| Error::UnexpectedEnd(self.end_span()))?;
+        *cursor += 1;
+        match element {
+            ParseElement::Node(Node::Literal(_) | Node::Variable { .. }) => Ok(index),
+            ParseElement::Node(Node::Instruction { operator, .. }) => {
+                let operator = match operator {
+                    Operator::Add => return self.parse_expression(cursor, Operator::UNARY_PRECEDENCE),
+                    Operator::Sub => Operator::Neg,
+                    Operator::Not => Operator::Not,
+                    _ => return Err(Error::UnexpectedToken(self.spans[index])),
                 };
-                let function_index = index;
-                while index < self.elements.len() {
-                    let element = &self.elements[index];
-                    match element {
-                        ParseElement::Node(
-                            Node::Literal(_) | Node::Variable { .. } | Node::Function { .. },
-                        ) if lowest_weight.weight == i16::MAX
-                            && match &self.elements[index + 1] {
-                                ParseElement::Token(token) => match token.kind() {
-                                    TokenKind::Bracket(Bracket::Closed) => true,
-                                    TokenKind::Special(Special::Comma) => true,
-                                    _ => false,
-                                },
-                                _ => false,
-                            } =>
-                        {
-                            if let ParseElement::Node(Node::Function { args, .. }) =
-                                &mut self.elements[function_index]
-                            {
-                                lowest_weight.index = index;
-                            }
-                        }
-                        // if element is instruction update weight and index
-                        ParseElement::Node(Node::Instruction { operator, .. }) => {
-                            if lowest_weight.weight >= operator.weight() {
-                                lowest_weight.weight = operator.weight();
-                                lowest_weight.index = index;
+                let operand = self.parse_expression(cursor, Operator::UNARY_PRECEDENCE)?;
+                self.elements[index] = ParseElement::Node(Node::Unary { operator, operand });
+                Ok(index)
+            }
+            ParseElement::Node(Node::Function { .. } | Node::Call { .. }) => {
+                self.expect(cursor, TokenKind::Bracket(Bracket::Opened))?;
+                let mut args = Vec::new();
+                if self.peek_kind(*cursor) == Some(TokenKind::Bracket(Bracket::Closed)) {
+                    *cursor += 1;
+                } else {
+                    loop {
+                        args.push(self.parse_expression(cursor, 0)?);
+                        match self.peek_kind(*cursor) {
+                            Some(TokenKind::Special(Special::Comma)) => *cursor += 1,
+                            Some(TokenKind::Bracket(Bracket::Closed)) => {
+                                *cursor += 1;
+                                break;
                             }
+                            Some(_) => return Err(Error::UnexpectedToken(self.spans[*cursor])),
+                            None => return Err(Error::UnmatchedBracket(self.spans[index + 1])),
                         }
-                        ParseElement::Token(token) => match token.kind() {
-                            // if element is comma add last lowest index to args and reset weight
-                            TokenKind::Special(Special::Comma) => {
-                                if let ParseElement::Node(Node::Function { args, .. }) =
-                                    &mut self.elements[function_index]
-                                {
-                                    args.push(lowest_weight.index);
-                                    lowest_weight.weight = i16::MAX;
-                                }
+                    }
+                }
+                let (arity_matches, node_args) = match &mut self.elements[index] {
+                    ParseElement::Node(Node::Function { function, args: node_args }) => {
+                        (function.arity().contains(args.len()), node_args)
+                    }
+                    ParseElement::Node(Node::Call { definition, args: node_args }) => {
+                        (self.definitions[*definition].params == args.len(), node_args)
+                    }
+                    _ => return Err(Error::InvalidIndex),
+                };
+                if !arity_matches {
+                    return Err(Error::InvalidArgCount(self.spans[index]));
+                }
+                *node_args = args;
+                Ok(index)
+            }
+            ParseElement::Node(Node::Conditional { .. }) => {
+                self.expect(cursor, TokenKind::Bracket(Bracket::Opened))?;
+                let condition = self.parse_expression(cursor, 0)?;
+                self.expect(cursor, TokenKind::Special(Special::Comma))?;
+                let then = self.parse_expression(cursor, 0)?;
+                self.expect(cursor, TokenKind::Special(Special::Comma))?;
+                let otherwise = self.parse_expression(cursor, 0)?;
+                self.expect(cursor, TokenKind::Bracket(Bracket::Closed))?;
+                self.elements[index] = ParseElement::Node(Node::Conditional {
+                    condition,
+                    then,
+                    otherwise,
+                });
+                Ok(index)
+            }
+            ParseElement::Node(
+                Node::Unary { .. }
+                    | Node::MulAdd { .. }
+                    | Node::Assign { .. }
+                    | Node::Sequence { .. }
+                    | Node::Tuple { .. },
+            ) => Err(Error::InvalidIndex),
+            ParseElement::Token(token) => match token.kind() {
+                TokenKind::Bracket(Bracket::Opened) => {
+                    let mut items = vec![self.parse_expression(cursor, 0)?];
+                    loop {
+                        match self.peek_kind(*cursor) {
+                            Some(TokenKind::Special(Special::Comma)) => {
+                                *cursor += 1;
+                                items.push(self.parse_expression(cursor, 0)?);
                             }
-                            // if element is bracket add last lowest index to args and break
-                            TokenKind::Bracket(Bracket::Closed) => {
-                                if let ParseElement::Node(Node::Function { args, .. }) =
-                                    &mut self.elements[function_index]
-                                {
-                                    args.push(lowest_weight.index);
-                                    break;
-                                }
+                            Some(TokenKind::Bracket(Bracket::Closed)) => {
+                                *cursor += 1;
+                                break;
                             }
-                            _ => {}
-                        },
-                        _ => (),
+                            Some(_) => return Err(Error::UnexpectedToken(self.spans[*cursor])),
+                            None => return Err(Error::UnmatchedBracket(self.spans[index])),
+                        }
+                    }
+                    if items.len() == 1 {
+                        Ok(items[0])
+                    } else {
+                        self.elements[index] = ParseElement::Node(Node::Tuple { items });
+                        Ok(index)
                     }
-                    index += 1;
                 }
+                _ => Err(Error::UnexpectedToken(self.spans[index])),
+            },
+        }
+    }
+    /// replaces the calls of defined functions below `top_node` by copies of their bodies
+    pub(crate) fn inline(&mut self) -> Result<&mut Self, Error> {
+        let top_node = self.top_node.ok_or(Error::NotCompiled)?;
+        let definitions = std::mem::take(&mut self.definitions);
+        let top_node = self.inline_recursive(&definitions, top_node);
+        self.definitions = definitions;
+        let top_node = top_node?;
+        self.top_node = Some(top_node);
+        // the bodies of the calls make the tree deeper
+        self.check_depth(top_node)?;
+        Ok(self)
+    }
+    /// returns an error with the span of a node nested more than `MAX_DEPTH` levels
+    /// below `top_node`, the passes after parsing recurse once per level
+    fn check_depth(&self, top_node: usize) -> Result<(), Error> {
+        // the deepest level every node was reached at, shared nodes are only visited again
+        // if they are reached at a deeper level
+        let mut depths = vec![0; self.elements.len()];
+        let mut pending = vec![(top_node, 1)];
+        while let Some((index, depth)) = pending.pop() {
+            if depth <= depths[index] {
+                continue;
+            }
+            if depth > MAX_DEPTH {
+                return Err(Error::TooDeep(self.spans[index]));
+            }
+            depths[index] = depth;
+            if let ParseElement::Node(node) = &self.elements[index] {
+                pending.extend(node.children().into_iter().map(|child| (child, depth + 1)));
             }
-            index += 1;
         }
         Ok(())
     }
+    /// returns the index of the subtree at `index` after inlining, which only changes for calls
+    fn inline_recursive(
+        &mut self,
+        definitions: &[Definition<T, LANES>],
+        index: usize,
+    ) -> Result<usize, Error> {
+        let (children, call) = match &self.elements[index] {
+            ParseElement::Node(node @ Node::Call { definition, .. }) => {
+                (node.children(), Some(*definition))
+            }
+            ParseElement::Node(node) => (node.children(), None),
+            ParseElement::Token(_) => return Err(Error::InvalidIndex),
+        };
+        let mut inlined = Vec::with_capacity(children.len());
+        for child in children {
+            inlined.push(self.inline_recursive(definitions, child)?);
+        }
+        if let Some(definition) = call {
+            let definition = &definitions[definition];
+            let span = self.spans[index];
+            return self.instantiate(definition, definition.root, &inlined, span);
+        }
+        if let ParseElement::Node(node) = &mut self.elements[index] {
+            for (child, inlined) in node.children_mut().into_iter().zip(inlined) {
+                *child = inlined;
+            }
+        }
+        Ok(index)
+    }
+    /// copies the subtree at `index` of the body of `definition` to the end of the elements,
+    /// with `args` in place of the parameters, and returns the index of the copy.
+    /// arguments used more than once are shared instead of copied
+    fn instantiate(
+        &mut self,
+        definition: &Definition<T, LANES>,
+        index: usize,
+        args: &[usize],
+        span: Span,
+    ) -> Result<usize, Error> {
+        let mut node = match &definition.elements[index] {
+            ParseElement::Node(Node::Variable { index }) if *index < definition.params => {
+                return Ok(args[*index]);
+            }
+            ParseElement::Node(Node::Variable { index }) => Node::Variable {
+                index: self.variables.find_or_set(&definition.variables[*index]),
+            },
+            ParseElement::Node(node) => node.clone(),
+            ParseElement::Token(_) => return Err(Error::InvalidIndex),
+        };
+        let mut copies = Vec::new();
+        for child in node.children() {
+            copies.push(self.instantiate(definition, child, args, span)?);
+        }
+        for (child, copy) in node.children_mut().into_iter().zip(copies) {
+            *child = copy;
+        }
+        self.elements.push(ParseElement::Node(node));
+        self.spans.push(span);
+        Ok(self.elements.len() - 1)
+    }
+    /// drops the elements that aren't part of the tree below `top_node`,
+    /// like the tokens and the operands of folded nodes. shared nodes stay shared
+    pub(crate) fn compact(&mut self) -> Result<&mut Self, Error> {
+        let top_node = self.top_node.ok_or(Error::NotCompiled)?;
+        let elements = std::mem::take(&mut self.elements);
+        let spans = std::mem::take(&mut self.spans);
+        let mut moved = vec![None; elements.len()];
+        let top_node = self.compact_recursive(&elements, &spans, &mut moved, top_node);
+        self.top_node = Some(top_node?);
+        Ok(self)
+    }
+    /// moves the subtree at `index` of `elements` to the end of the elements and returns its new index
+    fn compact_recursive(
+        &mut self,
+        elements: &[ParseElement<T, LANES>],
+        spans: &[Span],
+        moved: &mut [Option<usize>],
+        index: usize,
+    ) -> Result<usize, Error> {
+        if let Some(moved) = moved[index] {
+            return Ok(moved);
+        }
+        let mut node = match &elements[index] {
+            ParseElement::Node(node) => node.clone(),
+            ParseElement::Token(_) => return Err(Error::InvalidIndex),
+        };
+        let mut children = Vec::new();
+        for child in node.children() {
+            children.push(self.compact_recursive(elements, spans, moved, child)?);
+        }
+        for (child, moved) in node.children_mut().into_iter().zip(children) {
+            *child = moved;
+        }
+        self.elements.push(ParseElement::Node(node));
+        self.spans.push(spans[index]);
+        moved[index] = Some(self.elements.len() - 1);
+        Ok(self.elements.len() - 1)
+    }
+    /// moves the variables assigned by the script behind the ones that are inputs
+    pub(crate) fn sort_variables(&mut self) -> Result<&mut Self, Error> {
+        let mut assigned = vec![false; self.variables.values().len()];
+        for element in &self.elements {
+            if let ParseElement::Node(Node::Assign { index, .. }) = element {
+                assigned[*index] = true;
+            }
+        }
+        let mapping = self.variables.move_to_end(&assigned);
+        for element in &mut self.elements {
+            if let ParseElement::Node(Node::Variable { index } | Node::Assign { index, .. }) = element
+            {
+                *index = mapping[*index];
+            }
+        }
+        Ok(self)
+    }
+    /// merges the identical pure subtrees below `top_node` into one node,
+    /// turning the tree into a DAG whose shared nodes `emit` evaluates only once
+    pub(crate) fn share_subexpressions(&mut self) -> Result<&mut Self, Error> {
+        let top_node = self.top_node.ok_or(Error::NotCompiled)?;
+        let mut canonical = HashMap::new();
+        let mut visited = vec![None; self.elements.len()];
+        let (top_node, _) = self.share_recursive(top_node, &mut canonical, &mut visited)?;
+        self.top_node = Some(top_node);
+        Ok(self)
+    }
+    /// returns the index of the node identical to the one at `index` and whether it is pure,
+    /// a node is pure if it only depends on inputs and calls of `Function::is_const` functions
+    fn share_recursive(
+        &mut self,
+        index: usize,
+        canonical: &mut HashMap<ShareKey<LANES>, Vec<usize>>,
+        visited: &mut [Option<(usize, bool)>],
+    ) -> Result<(usize, bool), Error> {
+        if let Some(visited) = visited[index] {
+            return Ok(visited);
+        }
+        let ParseElement::Node(node) = &self.elements[index] else {
+            return Err(Error::InvalidIndex);
+        };
+        let mut pure = match node {
+            Node::Literal(_)
+            | Node::Instruction { .. }
+            | Node::Unary { .. }
+            | Node::MulAdd { .. }
+            | Node::Conditional { .. } => true,
+            Node::Variable { index } => *index < self.variables.inputs(),
+            Node::Function { function, .. } => function.is_const(),
+            Node::Call { .. } | Node::Assign { .. } | Node::Sequence { .. } | Node::Tuple { .. } => {
+                false
+            }
+        };
+        let mut children = Vec::new();
+        for child in node.children() {
+            let (child, child_pure) = self.share_recursive(child, canonical, visited)?;
+            pure &= child_pure;
+            children.push(child);
+        }
+        let ParseElement::Node(node) = &mut self.elements[index] else {
+            return Err(Error::InvalidIndex);
+        };
+        for (child, shared) in node.children_mut().into_iter().zip(children) {
+            *child = shared;
+        }
+        let shared = match ShareKey::of(node) {
+            Some(key) if pure => {
+                // calls with the same arguments are only the same if the functions are
+                let candidates = canonical.entry(key).or_default();
+                let same = candidates.iter().copied().find(|&candidate| {
+                    match (&self.elements[candidate], &self.elements[index]) {
+                        (
+                            ParseElement::Node(Node::Function { function, .. }),
+                            ParseElement::Node(Node::Function { function: other, .. }),
+                        ) => function.is_same(other),
+                        _ => true,
+                    }
+                });
+                same.unwrap_or_else(|| {
+                    candidates.push(index);
+                    index
+                })
+            }
+            _ => index,
+        };
+        visited[index] = Some((shared, pure));
+        Ok((shared, pure))
+    }
+    /// flattens the tree below `top_node` into the instruction tape
+    /// and computes the stack size needed to evaluate it
+    pub(crate) fn emit(&mut self) -> Result<&mut Self, Error> {
+        let top_node = self.top_node.ok_or(Error::NotCompiled)?;
+        self.instructions.clear();
+        self.variables.clear_temporaries();
+        let mut shared = Shared {
+            uses: vec![0; self.elements.len()],
+            stored: HashMap::new(),
+            branches: Vec::new(),
+            next_branch: 0,
+            eliminated: 0,
+        };
+        self.count_uses(top_node, &mut shared.uses);
+        self.emit_recursive(top_node, &mut shared)?;
+        self.eliminated = shared.eliminated;
+        let mut size = 0;
+        self.stack_size = 0;
+        for instruction in &self.instructions {
+            size += instruction.stack_effect();
+            self.stack_size = self.stack_size.max(size as usize);
+        }
+        self.frame = Mutex::new(Frame::with_capacity(self.stack_size));
+        Ok(self)
+    }
+    fn count_uses(&self, index: usize, uses: &mut [usize]) {
+        uses[index] += 1;
+        if uses[index] == 1 {
+            if let ParseElement::Node(node) = &self.elements[index] {
+                for child in node.children() {
+                    self.count_uses(child, uses);
+                }
+            }
+        }
+    }
+    /// emits the subtree at `index`, a node with more than one parent is stored in a temporary
+    /// the first time and loaded from it afterwards, unless the first time was in a branch
+    /// that might not have been taken
+    fn emit_recursive(&mut self, index: usize, shared: &mut Shared) -> Result<(), Error> {
+        let reused = shared.uses[index] > 1
+            && !matches!(
+                self.elements[index],
+                ParseElement::Node(Node::Literal(_) | Node::Variable { .. })
+            );
+        if let (true, Some((temporary, branches))) = (reused, shared.stored.get(&index)) {
+            if shared.branches.starts_with(branches) {
+                self.instructions.push(Instruction::Variable { index: *temporary });
+                shared.eliminated += 1;
+                return Ok(());
+            }
+        }
+        let instruction = match &self.elements[index] {
+            ParseElement::Node(Node::Instruction { operator, lhs, rhs }) => {
+                let (operator, lhs, rhs) = (*operator, *lhs, *rhs);
+                self.emit_recursive(lhs, shared)?;
+                if let Operator::And | Operator::Or = operator {
+                    let short_circuit = self.instructions.len();
+                    self.instructions.push(Instruction::ShortCircuit { operator, end: 0 });
+                    shared.enter_branch();
+                    self.emit_recursive(rhs, shared)?;
+                    shared.leave_branch();
+                    // past the `Binary` pushed below
+                    self.instructions[short_circuit] = Instruction::ShortCircuit {
+                        operator,
+                        end: self.instructions.len() + 1,
+                    };
+                } else {
+                    self.emit_recursive(rhs, shared)?;
+                }
+                Instruction::Binary { operator }
+            }
+            ParseElement::Node(Node::Unary { operator, operand }) => {
+                let (operator, operand) = (*operator, *operand);
+                self.emit_recursive(operand, shared)?;
+                Instruction::Unary { operator }
+            }
+            ParseElement::Node(Node::Literal(value)) => Instruction::Literal(*value),
+            ParseElement::Node(Node::Variable { index }) => Instruction::Variable { index: *index },
+            ParseElement::Node(Node::Function { function, args }) => {
+                let function = function.clone();
+                let args = args.clone();
+                for arg in args.iter() {
+                    self.emit_recursive(*arg, shared)?;
+                }
+                Instruction::Function {
+                    function,
+                    args: args.len(),
+                }
+            }
+            ParseElement::Node(Node::Conditional {
+                condition,
+                then,
+                otherwise,
+            }) => {
+                let (condition, then, otherwise) = (*condition, *then, *otherwise);
+                self.emit_recursive(condition, shared)?;
+                let branch = self.instructions.len();
+                self.instructions.push(Instruction::If { otherwise: 0 });
+                shared.enter_branch();
+                self.emit_recursive(then, shared)?;
+                shared.leave_branch();
+                let join = self.instructions.len();
+                self.instructions.push(Instruction::Else { end: 0 });
+                self.instructions[branch] = Instruction::If { otherwise: join + 1 };
+                shared.enter_branch();
+                self.emit_recursive(otherwise, shared)?;
+                shared.leave_branch();
+                self.instructions[join] = Instruction::Else {
+                    end: self.instructions.len() + 1,
+                };
+                Instruction::EndIf
+            }
+            ParseElement::Node(Node::MulAdd { lhs, rhs, addend }) => {
+                let operands = [*lhs, *rhs, *addend];
+                for operand in operands {
+                    self.emit_recursive(operand, shared)?;
+                }
+                Instruction::MulAdd
+            }
+            ParseElement::Node(Node::Assign { index, value }) => {
+                let (index, value) = (*index, *value);
+                self.emit_recursive(value, shared)?;
+                Instruction::Store { index }
+            }
+            ParseElement::Node(Node::Sequence { statements }) => {
+                let statements = statements.clone();
+                let (last, rest) = statements.split_last().ok_or(Error::InvalidIndex)?;
+                for statement in rest {
+                    self.emit_recursive(*statement, shared)?;
+                    self.instructions.push(Instruction::Pop);
+                }
+                return self.emit_recursive(*last, shared);
+            }
+            ParseElement::Node(Node::Tuple { items }) => {
+                for item in items.clone() {
+                    self.emit_recursive(item, shared)?;
+                }
+                return Ok(());
+            }
+            ParseElement::Node(Node::Call { .. }) | ParseElement::Token(_) => {
+                return Err(Error::InvalidIndex)
+            }
+        };
+        self.instructions.push(instruction);
+        if reused {
+            let temporary = match shared.stored.get(&index) {
+                Some((temporary, _)) => *temporary,
+                None => self.variables.push_temporary(),
+            };
+            self.instructions.push(Instruction::Store { index: temporary });
+            shared
+                .stored
+                .insert(index, (temporary, shared.branches.clone()));
+        }
+        Ok(())
+    }
+    /// returns the kind of the element at `index` if it is still a token
+    fn peek_kind(&self, index: usize) -> Option<TokenKind> {
+        match self.elements.get(index) {
+            Some(ParseElement::Token(token)) => Some(token.kind()),
+            _ => None,
+        }
+    }
+    /// advances `cursor` past a token of `kind` or returns an error
+    fn expect(&self, cursor: &mut usize, kind: TokenKind) -> Result<(), Error> {
+        if self.peek_kind(*cursor) == Some(kind) {
+            *cursor += 1;
+            Ok(())
+        } else if *cursor < self.elements.len() {
+            Err(Error::UnexpectedToken(self.spans[*cursor]))
+        } else {
+            Err(Error::UnexpectedEnd(self.end_span()))
+        }
+    }
+    /// the span just past the end of the expression string
+    fn end_span(&self) -> Span {
+        Span::new(self.expression.len(), self.expression.len())
+    }
     pub fn to_simd(&mut self) {
         for element in self.elements.iter_mut() {
             if let ParseElement::Node(node) = element {
@@ -313,6 +1093,11 @@ where
                 }
             }
         }
+        for instruction in self.instructions.iter_mut() {
+            if let Instruction::Literal(v) = instruction {
+                v.to_simd();
+            }
+        }
     }
     pub fn string(&self) -> &str {
         &self.expression
@@ -324,27 +1109,175 @@ where
 impl<'a, T: Function<T, LANES>, const LANES: usize> Expression<T, LANES>
 where
     T: Function<T, LANES> + Clone + Debug,
-    [(); T::MAX_ARGS]:,
     LaneCount<LANES>: SupportedLaneCount,
 {
     /// creates a new `Expression` from a string
     pub fn new(expression: String) -> Self {
+        Self::with_context(expression, Default::default())
+    }
+    /// creates a new `Expression` whose functions are resolved with `context`,
+    /// like a `FunctionRegistry` holding functions registered at runtime
+    pub fn with_context(expression: String, context: T::Context) -> Self {
         Self {
             elements: Vec::with_capacity(expression.len()),
+            spans: Vec::with_capacity(expression.len()),
             variables: Variables::with_capacity(expression.len() / 2),
             expression,
             top_node: None,
+            instructions: Vec::new(),
+            stack_size: 0,
+            context,
+            definitions: Vec::new(),
+            eliminated: 0,
+            depth: 0,
+            frame: Mutex::new(Frame::with_capacity(0)),
         }
     }
+    pub fn context(&self) -> &T::Context {
+        &self.context
+    }
+    /// changes to the context only take effect once the expression is compiled again
+    pub fn context_mut(&mut self) -> &mut T::Context {
+        &mut self.context
+    }
     /// Get a reference to the expression's elements.
     pub(crate) fn elements(&self) -> &[ParseElement<T, LANES>] {
         self.elements.as_ref()
     }
+    /// Get a reference to the expression's instruction tape.
+    pub(crate) fn instructions(&self) -> &[Instruction<T, LANES>] {
+        self.instructions.as_ref()
+    }
     fn clear(&mut self) {
         self.elements.clear();
+        self.spans.clear();
         self.variables.clear();
+        self.instructions.clear();
+        self.stack_size = 0;
         self.top_node = None
     }
+    /// defines a function in the expression language, like `f(x, y) = x^2 + y`,
+    /// that can be called from the expression like the functions of `T`
+    ///
+    /// the body may call the functions defined before it and use variables other than
+    /// the parameters, which are the variables of the calling expression.
+    /// calls are inlined when the expression is compiled, so a function can't call itself.
+    /// defining a function again replaces it, definitions are kept by `set_expression`
+    /// so they can be used by the following expressions as well.
+    /// the spans of the errors point into `definition`
+    pub fn define(&mut self, definition: &str) -> Result<(), Error> {
+        let mut body = Self::new(definition.to_string());
+        std::mem::swap(&mut body.context, &mut self.context);
+        std::mem::swap(&mut body.definitions, &mut self.definitions);
+        let definition = body.parse_definition();
+        std::mem::swap(&mut body.context, &mut self.context);
+        std::mem::swap(&mut body.definitions, &mut self.definitions);
+        let definition = definition?;
+        match self.definitions.iter_mut().find(|old| old.name == definition.name) {
+            Some(old) => *old = definition,
+            None => self.definitions.push(definition),
+        }
+        Ok(())
+    }
+    /// parses the `name(params) = body` in the expression string
+    fn parse_definition(&mut self) -> Result<Definition<T, LANES>, Error> {
+        if self.expression.is_empty() {
+            return Err(Error::EmptyExpression);
+        }
+        self.to_tokens()?;
+        let end = self.end_span();
+        let token = |cursor: usize| match self.elements.get(cursor) {
+            Some(ParseElement::Token(token)) => Ok(token),
+            _ => Err(Error::UnexpectedEnd(end)),
+        };
+        let head = token(0)?;
+        if head.kind() != TokenKind::Identifier(Identifier::Function) {
+            return Err(Error::UnexpectedToken(head.span()));
+        }
+        let name = head.slice(&self.expression).to_string();
+        let mut params: Vec<&str> = Vec::new();
+        let mut cursor = 2;
+        if token(cursor)?.kind() == TokenKind::Bracket(Bracket::Closed) {
+            cursor += 1;
+        } else {
+            loop {
+                let param = token(cursor)?;
+                let identifier = param.slice(&self.expression);
+                if param.kind() != TokenKind::Identifier(Identifier::Variable)
+                    || params.contains(&identifier)
+                {
+                    return Err(Error::UnexpectedToken(param.span()));
+                }
+                params.push(identifier);
+                let separator = token(cursor + 1)?;
+                cursor += 2;
+                match separator.kind() {
+                    TokenKind::Special(Special::Comma) => (),
+                    TokenKind::Bracket(Bracket::Closed) => break,
+                    _ => return Err(Error::UnexpectedToken(separator.span())),
+                }
+            }
+        }
+        // a single `=`, `==` is a comparison
+        let assign = token(cursor)?;
+        if assign.kind() != TokenKind::Operator(Operator::Equal)
+            || assign.span().end - assign.span().start != 1
+        {
+            return Err(Error::UnexpectedToken(assign.span()));
+        }
+        cursor += 1;
+        if cursor == self.elements.len() {
+            return Err(Error::UnexpectedEnd(end));
+        }
+        for (index, element) in self.elements.iter().enumerate().skip(cursor) {
+            if let ParseElement::Token(token) = element {
+                let namespaced = matches!(
+                    self.elements[index - 1],
+                    ParseElement::Token(ref previous)
+                        if previous.kind() == TokenKind::Special(Special::Namespace)
+                );
+                if token.kind() == TokenKind::Identifier(Identifier::Function)
+                    && token.slice(&self.expression) == name
+                    && !namespaced
+                {
+                    return Err(Error::RecursiveDefinition(token.span()));
+                }
+            }
+        }
+        let params = params.len();
+        let header = self.elements.drain(..cursor).collect::<Vec<_>>();
+        // the parameters are the first variables
+        for element in &header {
+            if let ParseElement::Token(token) = element {
+                if token.kind() == TokenKind::Identifier(Identifier::Variable) {
+                    self.variables.push(token.slice(&self.expression));
+                }
+            }
+        }
+        self.to_nodes::<4>()?.parse()?;
+        // the body is a single expression
+        let statement = self.elements.iter().position(|element| {
+            matches!(
+                element,
+                ParseElement::Node(Node::Assign { .. } | Node::Sequence { .. } | Node::Tuple { .. })
+            )
+        });
+        if let Some(index) = statement {
+            return Err(Error::UnexpectedToken(self.spans[index]));
+        }
+        self.inline()?;
+        let mut variables = vec![String::new(); self.variables.values().len()];
+        for (identifier, index) in self.variables.variables() {
+            variables[index] = identifier.to_string();
+        }
+        Ok(Definition {
+            name,
+            params,
+            elements: std::mem::take(&mut self.elements),
+            root: self.top_node.ok_or(Error::NotCompiled)?,
+            variables,
+        })
+    }
     /// set a new expression
     pub fn set_expression(&mut self, expression: String) {
         self.clear();
@@ -359,66 +1292,507 @@ where
             } else {
                 self.to_tokens()?
                     .to_nodes::<4>()?
-                    .set_indices()?
-                    .set_function_args()
+                    .parse()?
+                    .inline()?
+                    .sort_variables()?
+                    .share_subexpressions()?
+                    .compact()?
+                    .emit()?;
+                Ok(())
             }
         } else {
             Err(Error::AlreadyCompiled)
         }
     }
 
-    pub fn optimize(&mut self) -> Result<(), Error> {
+    /// folds the parts of the expression that don't depend on variables into literals,
+    /// including calls of functions for which `Function::is_const` holds,
+    /// applies the `OptimizationLevel::Safe` simplifications and emits the tape again
+    ///
+    /// returns the number of evaluations of repeated subexpressions the tape skips,
+    /// each of them is evaluated once and its value is reused
+    pub fn optimize(&mut self) -> Result<usize, Error> {
+        self.optimize_with(OptimizationLevel::Safe)
+    }
+    /// like `optimize` but with the simplifications of `level`, like `x * 1` to `x`,
+    /// `x - x` to `0` or `2 * a * 3` to `6 * a`
+    ///
+    /// the types of the variables aren't known ahead of time, they are assumed to be numbers
+    /// so `a * 1` is simplified even though it turns a bool into an int
+    pub fn optimize_with(&mut self, level: OptimizationLevel) -> Result<usize, Error> {
         if let Some(top_node) = self.top_node {
-            self.optimize_recursive(top_node)?;
-            Ok(())
+            let mut optimizer = Optimizer {
+                level,
+                values: HashMap::new(),
+                kinds: HashMap::new(),
+            };
+            self.optimize_recursive(top_node, &mut optimizer)?;
+            self.share_subexpressions()?.compact()?.emit()?;
+            Ok(self.eliminated)
         } else {
             Err(Error::NotCompiled)
         }
     }
-    fn optimize_recursive(&mut self, index: usize) -> Result<Option<Value<LANES>>, Error> {
+    /// folds the subtree at `index` in place and returns its value if it is constant,
+    /// a shared node is only optimized the first time
+    fn optimize_recursive(
+        &mut self,
+        index: usize,
+        optimizer: &mut Optimizer<LANES>,
+    ) -> Result<Option<Value<LANES>>, Error> {
+        if let Some(value) = optimizer.values.get(&index) {
+            return Ok(*value);
+        }
+        let value = self.optimize_node(index, optimizer)?;
+        optimizer.values.insert(index, value);
+        Ok(value)
+    }
+    fn optimize_node(
+        &mut self,
+        index: usize,
+        optimizer: &mut Optimizer<LANES>,
+    ) -> Result<Option<Value<LANES>>, Error> {
         if let ParseElement::Node(node) = &self.elements[index] {
             match node {
                 Node::Instruction { operator, lhs, rhs } => {
                     let operator = *operator;
                     let rhs = *rhs;
-                    let lhs = *lhs;
-                    if let (Ok(Some(lhs)), Ok(Some(rhs))) =
-                        (self.optimize_recursive(lhs), self.optimize_recursive(rhs))
-                    {
+                    let lhs = self.optimize_recursive(*lhs, optimizer)?;
+                    // like during evaluation the right side of `false && x` and `true || x`
+                    // is never looked at
+                    if let (Operator::And | Operator::Or, Some(lhs)) = (operator, lhs) {
+                        if lhs.all(operator == Operator::Or) {
+                            let value = lhs.as_bool();
+                            self.elements[index] = ParseElement::Node(Node::Literal(value));
+                            return Ok(Some(value));
+                        }
+                    }
+                    if let (Some(lhs), Some(rhs)) = (lhs, self.optimize_recursive(rhs, optimizer)?) {
                         let value = operator.eval(lhs, rhs);
                         self.elements[index] = ParseElement::Node(Node::Literal(value));
                         Ok(Some(value))
                     } else {
-                        Ok(None)
+                        self.simplify(index, optimizer)
                     }
                 }
+                Node::Unary { operator, operand } => {
+                    let operator = *operator;
+                    let operand = *operand;
+                    if let Some(value) = self.optimize_recursive(operand, optimizer)? {
+                        let value = operator.eval_unary(value);
+                        self.elements[index] = ParseElement::Node(Node::Literal(value));
+                        return Ok(Some(value));
+                    }
+                    // `--x` is `x` unless the negation turned a bool into an int
+                    if let ParseElement::Node(Node::Unary {
+                        operator: Operator::Neg,
+                        operand: inner,
+                    }) = self.elements[operand]
+                    {
+                        if operator == Operator::Neg && self.kind(inner, &mut optimizer.kinds) != Some(Literal::Bool) {
+                            self.elements[index] = self.elements[inner].clone();
+                        }
+                    }
+                    Ok(None)
+                }
                 Node::Literal(value) => Ok(Some(*value)),
+                Node::MulAdd { lhs, rhs, addend } => {
+                    let operands = [*lhs, *rhs, *addend];
+                    let mut values = [None; 3];
+                    for (value, operand) in values.iter_mut().zip(operands) {
+                        *value = self.optimize_recursive(operand, optimizer)?;
+                    }
+                    if let [Some(lhs), Some(rhs), Some(addend)] = values {
+                        let value = lhs.mul_add(rhs, addend);
+                        self.elements[index] = ParseElement::Node(Node::Literal(value));
+                        Ok(Some(value))
+                    } else {
+                        Ok(None)
+                    }
+                }
                 Node::Function { function, args } => {
                     let function = function.clone();
                     let args = args.clone();
-                    let mut args_eval = Stack::<Value<LANES>, { T::MAX_ARGS }>::new();
+                    // every argument is folded, even if the call can't be
+                    let mut args_eval = Vec::with_capacity(args.len());
                     for arg in args.iter() {
-                        let arg_eval = self.optimize_recursive(*arg)?;
-                        if let Some(arg) = None {
-                            args_eval.push(arg)
-                        } else {
-                            return Ok(None);
+                        args_eval.push(self.optimize_recursive(*arg, optimizer)?);
+                    }
+                    let args_eval: Option<Vec<_>> = args_eval.into_iter().collect();
+                    match args_eval {
+                        Some(args_eval) if function.is_const() => {
+                            let value = function.call(&args_eval)?;
+                            self.elements[index] = ParseElement::Node(Node::Literal(value));
+                            Ok(Some(value))
                         }
+                        _ => Ok(None),
                     }
-                    if function.is_const() {
-                        let value = function.call(args_eval.slice())?;
-                        self.elements[index] = ParseElement::Node(Node::Literal(value));
-                        Ok(Some(value))
-                    } else {
-                        Ok(None)
+                }
+                Node::Conditional {
+                    condition,
+                    then,
+                    otherwise,
+                } => {
+                    let branches = (*then, *otherwise);
+                    let condition = self.optimize_recursive(*condition, optimizer)?;
+                    let then = self.optimize_recursive(branches.0, optimizer)?;
+                    let otherwise = self.optimize_recursive(branches.1, optimizer)?;
+                    // a condition that is the same in every lane is replaced by the branch it takes
+                    let (branch, value) = match condition {
+                        Some(condition) if condition.all(true) => (branches.0, then),
+                        Some(condition) if condition.all(false) => (branches.1, otherwise),
+                        _ => return Ok(None),
+                    };
+                    self.elements[index] = self.elements[branch].clone();
+                    Ok(value)
+                }
+                // assignments have to happen even if their value is known
+                Node::Assign { value, .. } => {
+                    self.optimize_recursive(*value, optimizer)?;
+                    Ok(None)
+                }
+                Node::Sequence { statements: nodes } | Node::Tuple { items: nodes } => {
+                    for node in nodes.clone() {
+                        self.optimize_recursive(node, optimizer)?;
                     }
+                    Ok(None)
                 }
-                Node::Variable { .. } => Ok(None),
+                Node::Variable { .. } | Node::Call { .. } => Ok(None),
             }
         } else {
             Ok(None)
         }
     }
+    /// applies the algebraic identities to the instruction at `index` whose operands
+    /// are already optimized, returns its value if it became a literal
+    fn simplify(
+        &mut self,
+        index: usize,
+        optimizer: &mut Optimizer<LANES>,
+    ) -> Result<Option<Value<LANES>>, Error> {
+        let ParseElement::Node(Node::Instruction { operator, lhs, rhs }) = self.elements[index]
+        else {
+            return Err(Error::InvalidIndex);
+        };
+        let fast = optimizer.level == OptimizationLevel::FastMath;
+        let (lhs_kind, rhs_kind) = (self.kind(lhs, &mut optimizer.kinds), self.kind(rhs, &mut optimizer.kinds));
+        let is = |operand, constant, kind| self.is_constant(operand, constant, kind);
+        // the operand that is the result of the whole instruction
+        let identity = match operator {
+            // `-0.0 + 0` is `0.0`
+            Operator::Add if is(rhs, 0, lhs_kind) && Self::adds_zero(lhs_kind, fast) => Some(lhs),
+            Operator::Add if is(lhs, 0, rhs_kind) && Self::adds_zero(rhs_kind, fast) => Some(rhs),
+            Operator::Sub if is(rhs, 0, lhs_kind) && lhs_kind != Some(Literal::Bool) => Some(lhs),
+            Operator::Mul if is(rhs, 1, lhs_kind) && lhs_kind != Some(Literal::Bool) => Some(lhs),
+            Operator::Mul if is(lhs, 1, rhs_kind) && rhs_kind != Some(Literal::Bool) => Some(rhs),
+            Operator::Div if is(rhs, 1, lhs_kind) && lhs_kind != Some(Literal::Bool) => Some(lhs),
+            Operator::Pow if is(rhs, 1, None) => Some(lhs),
+            _ => None,
+        };
+        if let Some(operand) = identity {
+            self.elements[index] = self.elements[operand].clone();
+            return Ok(None);
+        }
+        let zero = |kind| match kind {
+            Some(Literal::Int | Literal::Bool) => Some(Value::Single(Single::Int(0))),
+            Some(Literal::Float) if fast => Some(Value::Single(Single::Float(0.0))),
+            _ => None,
+        };
+        // an int or float zero, the type of the result is the type of the instruction
+        let is_zero = |operand| is(operand, 0, Some(Literal::Float));
+        let constant = match operator {
+            Operator::Mul if is_zero(lhs) || is_zero(rhs) => zero(self.kind(index, &mut optimizer.kinds)),
+            Operator::Sub if self.same_subtree(lhs, rhs) => zero(self.kind(index, &mut optimizer.kinds)),
+            // ints to the power of 0 are floats like ints to any other exponent below 1
+            Operator::Pow if is(rhs, 0, None) => match lhs_kind {
+                Some(Literal::Int | Literal::Float) => Some(Value::Single(Single::Float(1.0))),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(value) = constant {
+            self.elements[index] = ParseElement::Node(Node::Literal(value));
+            return Ok(Some(value));
+        }
+        if matches!(operator, Operator::Add | Operator::Mul) && self.reassociate(index, optimizer) {
+            return self.simplify(index, optimizer);
+        }
+        match (operator, self.literal(rhs)) {
+            // `x^n` is computed by squaring like `powi`, so the multiplications give the same result
+            (Operator::Pow, Some(Value::Single(Single::Int(exponent @ 2..=16))))
+                if lhs_kind != Some(Literal::Bool) =>
+            {
+                self.expand_power(index, lhs, exponent as u32);
+            }
+            // unlike `powf` the square root of `-0.0` is `-0.0` and the one of `-inf` is `NaN`
+            (Operator::Pow, Some(Value::Single(Single::Float(exponent))))
+                if exponent == 0.5 && fast =>
+            {
+                self.elements[index] = ParseElement::Node(Node::Unary {
+                    operator: Operator::Sqrt,
+                    operand: lhs,
+                });
+            }
+            // the product isn't rounded before the addition
+            (Operator::Add, _) if fast => {
+                for (product, addend) in [(lhs, rhs), (rhs, lhs)] {
+                    if let ParseElement::Node(Node::Instruction {
+                        operator: Operator::Mul,
+                        lhs,
+                        rhs,
+                    }) = self.elements[product]
+                    {
+                        self.elements[index] =
+                            ParseElement::Node(Node::MulAdd { lhs, rhs, addend });
+                        break;
+                    }
+                }
+            }
+            _ => (),
+        }
+        Ok(None)
+    }
+    /// replaces `x^exponent` at `index` with a chain of multiplications of `base`
+    fn expand_power(&mut self, index: usize, base: usize, mut exponent: u32) {
+        let span = self.spans[index];
+        let mut power = base;
+        let mut result = None;
+        loop {
+            if exponent & 1 == 1 {
+                result = Some(match result {
+                    Some(result) => self.push_node(Operator::Mul, result, power, span),
+                    None => power,
+                });
+            }
+            exponent >>= 1;
+            if exponent == 0 {
+                break;
+            }
+            power = self.push_node(Operator::Mul, power, power, span);
+        }
+        if let Some(result) = result {
+            self.elements[index] = self.elements[result].clone();
+        }
+    }
+    /// adds the instruction `lhs operator rhs` to the elements and returns its index
+    fn push_node(&mut self, operator: Operator, lhs: usize, rhs: usize, span: Span) -> usize {
+        self.elements
+            .push(ParseElement::Node(Node::Instruction { operator, lhs, rhs }));
+        self.spans.push(span);
+        self.elements.len() - 1
+    }
+    /// turns `(x op c1) op c2` into `(c1 op c2) op x` for an associative and commutative `op`,
+    /// in any order of the operands. returns whether the instruction at `index` was changed
+    fn reassociate(&mut self, index: usize, optimizer: &mut Optimizer<LANES>) -> bool {
+        let ParseElement::Node(Node::Instruction { operator, lhs, rhs }) = self.elements[index]
+        else {
+            return false;
+        };
+        let (outer, inner) = match (self.literal(lhs), self.literal(rhs)) {
+            (Some(outer), None) => (outer, rhs),
+            (None, Some(outer)) => (outer, lhs),
+            _ => return false,
+        };
+        let ParseElement::Node(Node::Instruction {
+            operator: inner_operator,
+            lhs: inner_lhs,
+            rhs: inner_rhs,
+        }) = self.elements[inner]
+        else {
+            return false;
+        };
+        if inner_operator != operator {
+            return false;
+        }
+        let (constant, operand) = match (self.literal(inner_lhs), self.literal(inner_rhs)) {
+            (Some(constant), None) => (constant, inner_rhs),
+            (None, Some(constant)) => (constant, inner_lhs),
+            _ => return false,
+        };
+        // wrapping int arithmetic is associative, float arithmetic isn't
+        let exact = constant.kind() == Literal::Int
+            && outer.kind() == Literal::Int
+            && matches!(self.kind(operand, &mut optimizer.kinds), Some(Literal::Int | Literal::Bool));
+        if !exact && optimizer.level != OptimizationLevel::FastMath {
+            return false;
+        }
+        self.elements
+            .push(ParseElement::Node(Node::Literal(operator.eval(constant, outer))));
+        self.spans.push(self.spans[index]);
+        self.elements[index] = ParseElement::Node(Node::Instruction {
+            operator,
+            lhs: self.elements.len() - 1,
+            rhs: operand,
+        });
+        true
+    }
+    /// whether `x + 0` is `x` for `x` of type `kind`
+    fn adds_zero(kind: Option<Literal>, fast: bool) -> bool {
+        kind == Some(Literal::Int) || (fast && kind != Some(Literal::Bool))
+    }
+    /// whether the node at `index` is the literal `constant`,
+    /// as an int or as a float if the other operand is a float
+    fn is_constant(&self, index: usize, constant: i64, kind: Option<Literal>) -> bool {
+        match self.literal(index) {
+            Some(Value::Single(Single::Int(value))) => value == constant,
+            Some(Value::Single(Single::Float(value))) => {
+                kind == Some(Literal::Float) && value == constant as f64
+            }
+            _ => false,
+        }
+    }
+    fn literal(&self, index: usize) -> Option<Value<LANES>> {
+        match self.elements[index] {
+            ParseElement::Node(Node::Literal(value)) => Some(value),
+            _ => None,
+        }
+    }
+    /// the type the node at `index` evaluates to if it can be known without evaluating it,
+    /// rewrites don't change it so it is cached in `kinds`
+    fn kind(&self, index: usize, kinds: &mut HashMap<usize, Option<Literal>>) -> Option<Literal> {
+        if let Some(kind) = kinds.get(&index) {
+            return *kind;
+        }
+        let kind = self.node_kind(index, kinds);
+        kinds.insert(index, kind);
+        kind
+    }
+    fn node_kind(
+        &self,
+        index: usize,
+        kinds: &mut HashMap<usize, Option<Literal>>,
+    ) -> Option<Literal> {
+        let ParseElement::Node(node) = &self.elements[index] else {
+            return None;
+        };
+        match node {
+            Node::Literal(value) => Some(value.kind()),
+            Node::Unary {
+                operator: Operator::Neg,
+                operand,
+            } => match self.kind(*operand, kinds)? {
+                Literal::Float => Some(Literal::Float),
+                Literal::Int | Literal::Bool => Some(Literal::Int),
+            },
+            Node::Unary {
+                operator: Operator::Sqrt,
+                ..
+            } => Some(Literal::Float),
+            Node::Unary { .. } => Some(Literal::Bool),
+            Node::MulAdd { lhs, rhs, addend } => {
+                let kinds = [self.kind(*lhs, kinds), self.kind(*rhs, kinds), self.kind(*addend, kinds)];
+                if kinds.contains(&Some(Literal::Float)) {
+                    Some(Literal::Float)
+                } else if kinds.contains(&None) {
+                    None
+                } else {
+                    Some(Literal::Int)
+                }
+            }
+            Node::Instruction { operator, lhs, rhs } => {
+                let (lhs, rhs) = (self.kind(*lhs, kinds), self.kind(*rhs, kinds));
+                match operator {
+                    Operator::Add
+                    | Operator::Sub
+                    | Operator::Mul
+                    | Operator::Div
+                    | Operator::Mod => match (lhs, rhs) {
+                        (Some(Literal::Float), _) | (_, Some(Literal::Float)) => {
+                            Some(Literal::Float)
+                        }
+                        (Some(_), Some(_)) => Some(Literal::Int),
+                        _ => None,
+                    },
+                    // negative int exponents give floats
+                    Operator::Pow => match (lhs, rhs) {
+                        (Some(Literal::Bool), _) => Some(Literal::Bool),
+                        (Some(Literal::Float), _) | (Some(Literal::Int), Some(Literal::Float)) => {
+                            Some(Literal::Float)
+                        }
+                        (Some(Literal::Int), Some(Literal::Bool)) => Some(Literal::Int),
+                        _ => None,
+                    },
+                    _ => Some(Literal::Bool),
+                }
+            }
+            Node::Conditional {
+                then, otherwise, ..
+            } => {
+                let kind = self.kind(*then, kinds)?;
+                (self.kind(*otherwise, kinds)? == kind).then_some(kind)
+            }
+            _ => None,
+        }
+    }
+    /// whether the subtrees at `lhs` and `rhs` always evaluate to the same value,
+    /// a shared node is evaluated once so it is the same as itself. other function calls
+    /// are never the same since they might not be pure
+    fn same_subtree(&self, lhs: usize, rhs: usize) -> bool {
+        self.same_subtree_recursive(lhs, rhs, &mut HashMap::new())
+    }
+    fn same_subtree_recursive(
+        &self,
+        lhs: usize,
+        rhs: usize,
+        compared: &mut HashMap<(usize, usize), bool>,
+    ) -> bool {
+        if lhs == rhs {
+            return true;
+        }
+        if let Some(same) = compared.get(&(lhs, rhs)) {
+            return *same;
+        }
+        let same = self.same_node(lhs, rhs, compared);
+        compared.insert((lhs, rhs), same);
+        same
+    }
+    fn same_node(
+        &self,
+        lhs: usize,
+        rhs: usize,
+        compared: &mut HashMap<(usize, usize), bool>,
+    ) -> bool {
+        let (ParseElement::Node(lhs), ParseElement::Node(rhs)) =
+            (&self.elements[lhs], &self.elements[rhs])
+        else {
+            return false;
+        };
+        match (lhs, rhs) {
+            (Node::Literal(lhs), Node::Literal(rhs)) => lhs == rhs,
+            (Node::Variable { index: lhs }, Node::Variable { index: rhs }) => lhs == rhs,
+            (
+                Node::Unary { operator, operand },
+                Node::Unary {
+                    operator: rhs_operator,
+                    operand: rhs_operand,
+                },
+            ) => {
+                operator == rhs_operator
+                    && self.same_subtree_recursive(*operand, *rhs_operand, compared)
+            }
+            (Node::Instruction { .. }, Node::Instruction { .. })
+            | (Node::MulAdd { .. }, Node::MulAdd { .. })
+            | (Node::Conditional { .. }, Node::Conditional { .. }) => {
+                let same_operator = match (lhs, rhs) {
+                    (
+                        Node::Instruction { operator, .. },
+                        Node::Instruction {
+                            operator: rhs_operator,
+                            ..
+                        },
+                    ) => operator == rhs_operator,
+                    _ => true,
+                };
+                same_operator
+                    && lhs
+                        .children()
+                        .into_iter()
+                        .zip(rhs.children())
+                        .all(|(lhs, rhs)| self.same_subtree_recursive(lhs, rhs, compared))
+            }
+            _ => false,
+        }
+    }
     fn new_token_from_kind(&mut self, token_kind: TokenKind, start: usize) {
         let token = Token::new(token_kind, start);
         self.elements.push(ParseElement::Token(token));
@@ -435,110 +1809,140 @@ where
     ) -> Result<(), Error> {
         self.variables.set_by_index(index, value)
     }
-    fn new_token(&mut self, chr: u8, start: usize) -> Result<(), Error> {
+    /// returns the value of a variable, like one assigned by the last `eval` of a script
+    pub fn variable(&self, identifier: &str) -> Result<Value<LANES>, Error> {
+        Ok(self.variables[self.variables.index_of(identifier)?])
+    }
+    fn new_token(&mut self, chr: char, start: usize) -> Result<(), Error> {
         let token_kind = match chr {
-            b'0'..=b'9' => TokenKind::Literal(Literal::Int),
+            '0'..='9' => TokenKind::Literal(Literal::Int),
 
-            b'a'..=b'z' | b'A'..=b'Z' => TokenKind::Identifier(Identifier::Variable),
+            '+' => TokenKind::Operator(Operator::Add),
+            '-' => TokenKind::Operator(Operator::Sub),
+            '*' => TokenKind::Operator(Operator::Mul),
+            '/' => TokenKind::Operator(Operator::Div),
+            '%' => TokenKind::Operator(Operator::Mod),
+            '^' => TokenKind::Operator(Operator::Pow),
 
-            b'+' => TokenKind::Operator(Operator::Add),
-            b'-' => TokenKind::Operator(Operator::Sub),
-            b'*' => TokenKind::Operator(Operator::Mul),
-            b'/' => TokenKind::Operator(Operator::Div),
-            b'%' => TokenKind::Operator(Operator::Mod),
-            b'^' => TokenKind::Operator(Operator::Pow),
+            '=' => TokenKind::Operator(Operator::Equal),
+            '!' => TokenKind::Operator(Operator::Not),
+            '>' => TokenKind::Operator(Operator::Greater),
+            '<' => TokenKind::Operator(Operator::Smaller),
+            '&' => TokenKind::Operator(Operator::And),
+            '|' => TokenKind::Operator(Operator::Or),
+            '#' => TokenKind::Operator(Operator::Xor),
 
-            b'=' => TokenKind::Operator(Operator::Equal),
-            b'!' => TokenKind::Operator(Operator::Not),
-            b'>' => TokenKind::Operator(Operator::Greater),
-            b'<' => TokenKind::Operator(Operator::Smaller),
-            b'&' => TokenKind::Operator(Operator::And),
-            b'|' => TokenKind::Operator(Operator::Or),
-            b'#' => TokenKind::Operator(Operator::Xor),
+            '(' => TokenKind::Bracket(Bracket::Opened),
+            ')' => TokenKind::Bracket(Bracket::Closed),
 
-            b'(' => TokenKind::Bracket(Bracket::Opened),
-            b')' => TokenKind::Bracket(Bracket::Closed),
+            '?' => TokenKind::Special(Special::Question),
+            ':' => TokenKind::Special(Special::Colon),
+            ';' => TokenKind::Special(Special::Semicolon),
 
-            b'.' => TokenKind::Literal(Literal::Float),
+            '.' => TokenKind::Literal(Literal::Float),
 
-            _ => return Err(Error::UnkownCharacter(chr as char)),
+            chr if is_identifier_start(chr) => TokenKind::Identifier(Identifier::Variable),
+
+            _ => {
+                return Err(Error::UnkownCharacter(
+                    chr,
+                    Span::new(start, start + chr.len_utf8()),
+                ))
+            }
         };
-        let token = Token::new(token_kind, start);
+        let mut token = Token::new(token_kind, start);
+        token.set_end(start + chr.len_utf8());
         self.elements.push(ParseElement::Token(token));
         Ok(())
     }
-    fn insert_neg(&mut self, index: usize) {
-        self.elements
-            .push(ParseElement::Token(Token::new_neg_zero()));
-        self.elements.push(ParseElement::Token(Token::new(
-            TokenKind::Operator(Operator::Sub),
-            index,
-        )))
-    }
-    fn push(&mut self, chr: u8, index: usize) -> Result<(), Error> {
+    fn push(&mut self, chr: char, index: usize) -> Result<(), Error> {
         if let Some(ParseElement::Token(token)) = self.elements.last_mut() {
+            // numbers and identifiers only continue if there is no space in between
+            let adjacent = token.span().end == index;
+            // exponents, radix prefixes and digit separators
+            if let TokenKind::Literal(kind @ (Literal::Int | Literal::Float)) = token.kind() {
+                if adjacent {
+                    if let Some(kind) = continue_number(token.slice(&self.expression), kind, chr) {
+                        token.set_inc(TokenKind::Literal(kind));
+                        return Ok(());
+                    }
+                }
+            }
             match chr {
-                b' ' => (),
-                b':' => match token.kind() {
-                    TokenKind::Identifier(_) => {
+                chr if chr.is_whitespace() => (),
+                // `:` directly after a name is a namespace, otherwise it belongs to `?`
+                ':' => match token.kind() {
+                    TokenKind::Identifier(_) if adjacent => {
                         token.set_kind(TokenKind::Special(Special::Namespace));
                     }
-                    _ => return Err(Error::UnexpectedToken),
+                    _ => self.new_token(chr, index)?,
                 },
-                b'0'..=b'9' => match token.kind() {
-                    TokenKind::Identifier(_) | TokenKind::Literal(_) => token.inc_end(),
+                '0'..='9' => match token.kind() {
+                    TokenKind::Identifier(_) | TokenKind::Literal(_) if adjacent => token.inc_end(),
                     _ => self.new_token_from_kind(TokenKind::Literal(Literal::Int), index),
                 },
-                b'a'..=b'z' | b'A'..=b'Z' => match token.kind() {
-                    TokenKind::Identifier(_) => token.inc_end(),
-                    _ => {
-                        self.new_token_from_kind(TokenKind::Identifier(Identifier::Variable), index)
-                    }
-                },
-                b'.' => match token.kind() {
+                '.' => match token.kind() {
                     TokenKind::Literal(Literal::Int) => {
                         token.set_inc(TokenKind::Literal(Literal::Float))
                     }
                     _ => self.new_token_from_kind(TokenKind::Literal(Literal::Float), index),
                 },
-                b'+' | b'-' | b'*' | b'/' | b'%' | b'^' | b'&' | b'|' | b'!' | b'=' | b'<'
-                | b'>' | b'#' => match (chr, token.kind()) {
-                    (b'-', TokenKind::Operator(_) | TokenKind::Bracket(_)) => {
-                        self.insert_neg(index);
-                    }
-                    (b'>', TokenKind::Operator(Operator::Equal)) => {
-                        token.set_inc(TokenKind::Operator(Operator::GreaterEqual));
-                    }
-                    (b'<', TokenKind::Operator(Operator::Equal)) => {
-                        token.set_inc(TokenKind::Operator(Operator::SmallerEqual));
-                    }
-                    (b'!', TokenKind::Operator(Operator::Equal)) => {
-                        token.set_inc(TokenKind::Operator(Operator::NotEqual));
+                '+' | '-' | '*' | '/' | '%' | '^' | '&' | '|' | '!' | '=' | '<' | '>' | '#' => {
+                    match (chr, token.kind()) {
+                        // two character operators only join if there is no space between them
+                        // and the first is a single character, `a==!b` is `a == !b`
+                        _ if !adjacent || token.span().end - token.span().start != 1 => {
+                            self.new_token(chr, index)?
+                        }
+                        // the older spellings `=>`, `=<` and `=!` are still accepted
+                        ('=', TokenKind::Operator(Operator::Greater))
+                        | ('>', TokenKind::Operator(Operator::Equal)) => {
+                            token.set_inc(TokenKind::Operator(Operator::GreaterEqual));
+                        }
+                        ('=', TokenKind::Operator(Operator::Smaller))
+                        | ('<', TokenKind::Operator(Operator::Equal)) => {
+                            token.set_inc(TokenKind::Operator(Operator::SmallerEqual));
+                        }
+                        ('=', TokenKind::Operator(Operator::Not))
+                        | ('!', TokenKind::Operator(Operator::Equal)) => {
+                            token.set_inc(TokenKind::Operator(Operator::NotEqual));
+                        }
+                        // `==`, `&&` and `||` are the same operators as `=`, `&` and `|`
+                        ('=', TokenKind::Operator(Operator::Equal))
+                        | ('&', TokenKind::Operator(Operator::And))
+                        | ('|', TokenKind::Operator(Operator::Or)) => token.inc_end(),
+                        _ => self.new_token(chr, index)?,
                     }
-                    _ => self.new_token(chr, index)?,
-                },
-                b'(' | b')' => match (chr, token.kind()) {
-                    (b'(', TokenKind::Identifier(_)) => {
+                }
+                '(' | ')' => match (chr, token.kind()) {
+                    ('(', TokenKind::Identifier(_)) => {
                         token.set_kind(TokenKind::Identifier(Identifier::Function));
                         self.new_token_from_kind(TokenKind::Bracket(Bracket::Opened), index)
                     }
-                    (b'(', _) => {
+                    ('(', _) => {
                         self.new_token_from_kind(TokenKind::Bracket(Bracket::Opened), index)
                     }
-                    (b')', _) => {
+                    (')', _) => {
                         self.new_token_from_kind(TokenKind::Bracket(Bracket::Closed), index)
                     }
                     _ => unreachable!(),
                 },
-                b',' => self.new_token_from_kind(TokenKind::Special(Special::Comma), index),
-                _ => return Err(Error::UnexpectedToken),
-            }
-        } else {
-            if chr == b'-' {
-                self.insert_neg(index);
-            } else {
-                self.new_token(chr, index)?;
+                ',' => self.new_token_from_kind(TokenKind::Special(Special::Comma), index),
+                ';' => self.new_token_from_kind(TokenKind::Special(Special::Semicolon), index),
+                '?' => self.new_token(chr, index)?,
+                chr if is_identifier_continue(chr) => match token.kind() {
+                    TokenKind::Identifier(_) if adjacent => token.set_end(index + chr.len_utf8()),
+                    _ => self.new_token(chr, index)?,
+                },
+                _ => {
+                    return Err(Error::UnkownCharacter(
+                        chr,
+                        Span::new(index, index + chr.len_utf8()),
+                    ))
+                }
             }
+        } else if !chr.is_whitespace() {
+            self.new_token(chr, index)?;
         }
         Ok(())
     }
@@ -547,36 +1951,156 @@ where
 impl<'a, T: Function<T, LANES>, const LANES: usize> Expression<T, LANES>
 where
     T: Function<T, LANES> + Clone + Debug,
-    [(); T::MAX_ARGS]:,
     LaneCount<LANES>: SupportedLaneCount,
 {
+    /// runs the instruction tape on a stack of values
+    ///
+    /// the expression isn't changed, the variables assigned by a script
+    /// are only kept by `eval_assign`
     #[inline]
     pub fn eval(&self) -> Result<Value<LANES>, Error> {
-        if let Some(top_node) = self.top_node {
-            self.eval_recursive(top_node)
-        } else {
-            Err(Error::NotCompiled)
+        if self.result_count() != 1 {
+            return Err(Error::ResultCount);
         }
+        self.run(|frame| frame.results().last().copied().ok_or(Error::InvalidIndex))?
     }
-    fn eval_recursive(&self, index: usize) -> Result<Value<LANES>, Error> {
-        if let ParseElement::Node(n) = &self.elements[index] {
-            Ok(match n {
-                Node::Instruction { operator, lhs, rhs } => {
-                    operator.eval(self.eval_recursive(*lhs)?, self.eval_recursive(*rhs)?)
-                }
-                Node::Literal(value) => *value,
-                Node::Variable { index } => self.variables[*index],
-                Node::Function { function, args } => {
-                    let mut args_eval = Stack::<Value<LANES>, { T::MAX_ARGS }>::new();
-                    for arg in args.iter() {
-                        args_eval.push(self.eval_recursive(*arg)?);
-                    }
-                    function.call(args_eval.slice())?
+    /// like `eval` but writes the variables assigned by the script into the expression,
+    /// so `variable` returns their values afterwards
+    pub fn eval_assign(&mut self) -> Result<Value<LANES>, Error> {
+        if self.result_count() != 1 {
+            return Err(Error::ResultCount);
+        }
+        if self.top_node.is_none() {
+            return Err(Error::NotCompiled);
+        }
+        let frame = self.frame.get_mut().unwrap_or_else(PoisonError::into_inner);
+        frame.load(self.variables.values());
+        frame.run(&self.instructions)?;
+        self.variables.values_mut().copy_from_slice(frame.values());
+        frame.results().last().copied().ok_or(Error::InvalidIndex)
+    }
+    /// evaluates an expression returning a tuple and writes its items into `results`
+    pub fn eval_tuple(&self, results: &mut [Value<LANES>]) -> Result<(), Error> {
+        if results.len() != self.result_count() {
+            return Err(Error::ResultCount);
+        }
+        self.run(|frame| results.copy_from_slice(frame.results()))
+    }
+    /// runs the instruction tape with the variable values of the expression
+    /// and passes the frame holding the results to `read`
+    ///
+    /// the frame of the expression is reused unless another thread is evaluating it
+    fn run<R>(&self, read: impl FnOnce(&Frame<LANES>) -> R) -> Result<R, Error> {
+        if self.top_node.is_none() {
+            return Err(Error::NotCompiled);
+        }
+        let mut guard = match self.frame.try_lock() {
+            Ok(guard) => Some(guard),
+            // the frame is loaded again before every run, a panic in an earlier one doesn't matter
+            Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        };
+        let mut fresh;
+        let frame = match &mut guard {
+            Some(guard) => &mut **guard,
+            None => {
+                fresh = Frame::with_capacity(self.stack_size);
+                &mut fresh
+            }
+        };
+        frame.load(self.variables.values());
+        frame.run(&self.instructions)?;
+        Ok(read(frame))
+    }
+    /// creates an immutable `Program` from the compiled expression
+    /// that can be shared between threads and evaluated with a `Frame` each
+    pub fn program(&self) -> Result<Program<T, LANES>, Error> {
+        if self.top_node.is_none() {
+            return Err(Error::NotCompiled);
+        }
+        Ok(Program::new(
+            self.instructions.clone(),
+            self.variables.clone(),
+            self.stack_size,
+            self.result_count(),
+        ))
+    }
+    /// creates a compiled copy of the expression in which the variables named in `bindings`
+    /// are replaced by their values and optimizes it, so it only has the remaining variables
+    ///
+    /// only inputs can be bound, not variables assigned by the script.
+    /// the copy has a default context, the functions it calls are already resolved
+    pub fn specialize(&self, bindings: &[(&str, Value<LANES>)]) -> Result<Self, Error> {
+        let top_node = self.top_node.ok_or(Error::NotCompiled)?;
+        let inputs = self.variables.inputs();
+        let mut bound = vec![None; self.variables.values().len()];
+        for (identifier, value) in bindings {
+            let index = self.variables.index_of(identifier)?;
+            if index >= inputs {
+                return Err(Error::InvalidVariable);
+            }
+            bound[index] = Some(*value);
+        }
+        // the temporaries have no name and are created again by `optimize`
+        let mut names = vec![None; bound.len()];
+        for (identifier, index) in self.variables.variables() {
+            names[index] = Some(identifier);
+        }
+        // the nodes are renumbered through the same names `set_variable` looks up,
+        // after the assigned variables are moved behind the inputs
+        let mut variables = Variables::with_capacity(names.len());
+        let mut mapping = vec![0; names.len()];
+        let mut assigned = Vec::new();
+        let mut kept = Vec::new();
+        for (index, identifier) in names.into_iter().enumerate() {
+            if let (None, Some(identifier)) = (bound[index], identifier) {
+                mapping[index] = variables.find_or_set(identifier);
+                variables[mapping[index]] = self.variables[index];
+                assigned.push(index >= inputs);
+                kept.push(index);
+            }
+        }
+        let moved = variables.move_to_end(&assigned);
+        for index in kept {
+            mapping[index] = moved[mapping[index]];
+        }
+        let elements = self
+            .elements
+            .iter()
+            .map(|element| match element {
+                ParseElement::Node(Node::Variable { index }) => ParseElement::Node(
+                    match bound[*index] {
+                        Some(value) => Node::Literal(value),
+                        None => Node::Variable {
+                            index: mapping[*index],
+                        },
+                    },
+                ),
+                ParseElement::Node(Node::Assign { index, value }) => {
+                    ParseElement::Node(Node::Assign {
+                        index: mapping[*index],
+                        value: *value,
+                    })
                 }
+                element => element.clone(),
             })
-        } else {
-            Err(Error::InvalidIndex)
-        }
+            .collect();
+        let mut specialized = Self {
+            elements,
+            spans: self.spans.clone(),
+            variables,
+            expression: self.expression.clone(),
+            top_node: Some(top_node),
+            instructions: Vec::new(),
+            stack_size: 0,
+            context: Default::default(),
+            definitions: self.definitions.clone(),
+            eliminated: 0,
+            depth: 0,
+            frame: Mutex::new(Frame::with_capacity(0)),
+        };
+        specialized.optimize()?;
+        Ok(specialized)
     }
     fn get_operands(&self, index: usize) -> Option<(usize, usize)> {
         self.elements[index].get_operands_indices()
@@ -588,14 +2112,59 @@ impl<'a, T, const LANES: usize> Default for Expression<T, LANES>
 where
     LaneCount<LANES>: SupportedLaneCount,
     T: Function<T, LANES>,
-    [(); T::MAX_ARGS]:,
 {
     fn default() -> Self {
         Self {
             elements: Default::default(),
+            spans: Default::default(),
             variables: Default::default(),
             expression: Default::default(),
             top_node: Default::default(),
+            instructions: Default::default(),
+            stack_size: Default::default(),
+            context: Default::default(),
+            definitions: Default::default(),
+            eliminated: Default::default(),
+            depth: Default::default(),
+            frame: Mutex::new(Frame::with_capacity(0)),
         }
     }
 }
+
+/// returns the kind of the number literal `literal` after `chr` is appended to it,
+/// or `None` if `chr` can't continue it
+///
+/// handles the `0x` and `0b` prefixes, hexadecimal digits, exponents like `1e-6`
+/// and `_` digit separators, plain digits and `.` are handled by the tokenizer itself
+fn continue_number(literal: &str, kind: Literal, chr: char) -> Option<Literal> {
+    let prefix = literal.get(..2);
+    match chr {
+        '_' => Some(kind),
+        'x' | 'X' | 'b' | 'B' if literal == "0" => Some(Literal::Int),
+        _ if matches!(prefix, Some("0x" | "0X")) => chr.is_ascii_hexdigit().then_some(kind),
+        _ if matches!(prefix, Some("0b" | "0B")) => None,
+        'e' | 'E' if !literal.contains(['e', 'E']) => Some(Literal::Float),
+        '+' | '-' if literal.ends_with(['e', 'E']) => Some(kind),
+        _ => None,
+    }
+}
+
+/// whether `chr` can start an identifier, `_` and the alphabetic characters of any script
+/// like in `größe` or `π`. symbols like `°` or `×` aren't part of identifiers
+fn is_identifier_start(chr: char) -> bool {
+    chr == '_' || chr.is_alphabetic()
+}
+
+/// whether `chr` can continue an identifier, the start characters and digits
+fn is_identifier_continue(chr: char) -> bool {
+    is_identifier_start(chr) || chr.is_alphanumeric()
+}
+
+/// parses decimal, `0x` hexadecimal and `0b` binary integer literals
+fn parse_int(literal: &str) -> Option<i64> {
+    match literal.get(..2) {
+        Some("0x" | "0X") => i64::from_str_radix(&literal[2..], 16).ok(),
+        Some("0b" | "0B") => i64::from_str_radix(&literal[2..], 2).ok(),
+        _ => literal.parse().ok(),
+    }
+}
@@ -1,6 +1,6 @@
 #[macro_export]
 macro_rules! impl_functions {
-    ($lib: ident: $lib_namespace: ident; [$($import: ty: $import_namespace: ident),*]; [$($func_name: ident: $func: ident($arg_count: expr) $(;$is_const: expr)?),+]) => {
+    ($lib: ident: $lib_namespace: ident; [$($import: ty: $import_namespace: ident),*]; [$($func_name: ident: $func: ident($arity: expr) $(;$is_const: expr)?),+]) => {
         #[allow(non_camel_case_types)]
         #[derive(Debug, Clone)]
         pub enum $lib<const LANES: usize>
@@ -11,27 +11,37 @@ macro_rules! impl_functions {
         impl<const LANES: usize> $crate::evaluate::function::Function<$lib<LANES>, LANES> for $lib<LANES>
         where LaneCount<LANES>: SupportedLaneCount{
             const NAMESPACE: &'static str = stringify!($lib_namespace);
-            const MAX_ARGS: usize = $crate::biggest!($($arg_count),+);
+            type Context = ();
             fn from_string(
                 namespaces: &mut std::slice::Iter<&str>,
                 identifier: &str,
             ) -> Result<$lib<LANES>, $crate::error::Error> {
-                if let Some(&namespace) = dbg!(namespaces.next()) {
+                if let Some(&namespace) = namespaces.next() {
                     Ok(match namespace {
                         $(<$import>::NAMESPACE => $lib::$import_namespace(<$import>::from_string(namespaces, identifier)?),)*
                         Self::NAMESPACE => Self::from_string(namespaces, identifier)?,
-                        _ => return Err($crate::error::Error::InvalidNamespace)
+                        _ => return Err($crate::error::Error::InvalidNamespace(Default::default()))
                     })
                 } else {
-                    Ok(match dbg!(identifier) {
+                    Ok(match identifier {
                         $(stringify!($func) => $lib::$func_name,)+
-                        _ => return  Err($crate::error::Error::UnexpectedToken)
+                        _ => return Err($crate::error::Error::NoIdentifierMatch(Default::default()))
                     })
                 }
             }
+            fn arity(&self) -> $crate::evaluate::function::Arity {
+                match self {
+                    $($lib::$func_name => $crate::evaluate::function::Arity::from($arity),)+
+                    $($lib::$import_namespace(i) => i.arity(),)*
+                }
+            }
+            // functions with a fixed arity take an array, the others a slice
             fn call(&self, args: &[$crate::evaluate::value::Value<LANES>]) -> Result<$crate::evaluate::value::Value<LANES>, $crate::error::Error> {
+                if !self.arity().contains(args.len()) {
+                    return Err($crate::error::Error::InvalidArgs);
+                }
                 Ok(match self {
-                    $($lib::$func_name => { if args.len() == $arg_count {$func(args.try_into()?)} else {return Err($crate::error::Error::InvalidArgs)}},)+
+                    $($lib::$func_name => $func(args.try_into()?),)+
                     $($lib::$import_namespace(i) => i.call(args)?,)*
                 })
             }
@@ -41,6 +51,12 @@ macro_rules! impl_functions {
                     $($lib::$func_name => $crate::empty_or_input!($($is_const)?),)*
                 }
             }
+            fn is_same(&self, other: &$lib<LANES>) -> bool {
+                match (self, other) {
+                    $(($lib::$import_namespace(i), $lib::$import_namespace(other)) => i.is_same(other),)*
+                    _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+                }
+            }
         }
     }
 }
@@ -61,14 +77,14 @@ macro_rules! impl_functions_test {
                 namespaces: &mut std::slice::Iter<&str>,
                 identifier: &str,
             ) -> Result<$lib<LANES>, $crate::error::SimdevalError> {
-                if let Some(&namespace) = dbg!(namespaces.next()) {
+                if let Some(&namespace) = namespaces.next() {
                     Ok(match namespace {
                         $(<$import>::NAMESPACE => $lib::$import_namespace(<$import>::from_string(namespaces, identifier)?),)+
                         Self::NAMESPACE => Self::from_string(namespaces, identifier)?,
                         _ => return Err($crate::error::SimdevalError::InvalidNamespace)
                     })
                 } else {
-                    Ok(match dbg!(identifier) {
+                    Ok(match identifier {
                         $(stringify!($func) => $lib::$func,)*
                         _ => return  Err($crate::error::SimdevalError::UnexpectedToken)
                     })
@@ -103,14 +119,14 @@ macro_rules! impl_functions_test2 {
                 namespaces: &mut std::slice::Iter<&str>,
                 identifier: &str,
             ) -> Result<$lib, $crate::error::SimdevalError> {
-                if let Some(&namespace) = dbg!(namespaces.next()) {
+                if let Some(&namespace) = namespaces.next() {
                     Ok(match namespace {
                         $(<$import>::NAMESPACE => $lib::$import_namespace(<$import>::from_string(namespaces, identifier)?),)+
                         Self::NAMESPACE => Self::from_string(namespaces, identifier)?,
                         _ => return Err($crate::error::SimdevalError::InvalidNamespace)
                     })
                 } else {
-                    Ok(match dbg!(identifier) {
+                    Ok(match identifier {
                         $(stringify!($func) => $lib::$func,)*
                         _ => return  Err($crate::error::SimdevalError::UnexpectedToken)
                     })
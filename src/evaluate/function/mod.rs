@@ -1,4 +1,9 @@
-use ::std::{slice::Iter, simd::{LaneCount, SupportedLaneCount}};
+use ::std::{
+    fmt::Debug,
+    ops::{RangeFrom, RangeInclusive},
+    simd::{LaneCount, SupportedLaneCount},
+    slice::Iter,
+};
 
 use crate::error::Error;
 
@@ -7,6 +12,7 @@ use super::value::Value;
 
 pub mod std;
 pub mod macros;
+pub mod registry;
 
 
 pub trait Function<T, const LANES: usize>
@@ -15,10 +21,67 @@ where
     LaneCount<LANES>: SupportedLaneCount
 {
     const NAMESPACE: &'static str;
-    const MAX_ARGS: usize;
+    /// the state needed to resolve identifiers, `()` for functions known at compile time
+    type Context: Default + Debug;
     fn from_string(namespaces: &mut Iter<&str>, identifier: &str) -> Result<T, Error>;
+    /// resolves a function like `from_string` but may look it up in `context`
+    fn resolve(
+        context: &Self::Context,
+        namespaces: &mut Iter<&str>,
+        identifier: &str,
+    ) -> Result<T, Error> {
+        Self::from_string(namespaces, identifier)
+    }
+    /// the number of arguments `call` accepts, checked when the expression is compiled
+    fn arity(&self) -> Arity;
     fn call(&self, args: &[Value<LANES>]) -> Result<Value<LANES>, Error>;
     fn is_const(&self) -> bool {
         true
     }
+    /// whether `self` and `other` are the same function, calls of the same function
+    /// with the same arguments are evaluated once. never the same by default
+    fn is_same(&self, other: &T) -> bool {
+        false
+    }
+}
+
+/// the number of arguments a function accepts, `max` is `None` for functions
+/// accepting any number of at least `min` arguments
+///
+/// created from a count like `2`, an inclusive range like `2..=4` or an open range like `1..`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arity {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub fn contains(&self, count: usize) -> bool {
+        count >= self.min && self.max.is_none_or(|max| count <= max)
+    }
+}
+
+impl From<usize> for Arity {
+    fn from(count: usize) -> Self {
+        Self {
+            min: count,
+            max: Some(count),
+        }
+    }
+}
+impl From<RangeInclusive<usize>> for Arity {
+    fn from(range: RangeInclusive<usize>) -> Self {
+        Self {
+            min: *range.start(),
+            max: Some(*range.end()),
+        }
+    }
+}
+impl From<RangeFrom<usize>> for Arity {
+    fn from(range: RangeFrom<usize>) -> Self {
+        Self {
+            min: range.start,
+            max: None,
+        }
+    }
 }
@@ -0,0 +1,216 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    simd::{LaneCount, SupportedLaneCount},
+    slice::Iter,
+    sync::Arc,
+};
+
+use crate::{error::Error, evaluate::value::Value};
+
+use super::{Arity, Function};
+
+type Closure<const LANES: usize> =
+    dyn Fn(&[Value<LANES>]) -> Result<Value<LANES>, Error> + Send + Sync;
+
+/// functions registered at runtime as rust closures, looked up before the builtin functions `F`
+///
+/// the registry is the context of an expression over `RegistryFunction<F, LANES>`,
+/// which is passed to `Expression::with_context`. functions registered after the expression
+/// was compiled are only found when it is compiled again
+pub struct FunctionRegistry<F, const LANES: usize>
+where
+    F: Function<F, LANES>,
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    functions: HashMap<(String, String), Arc<Registered<LANES>>>,
+    builtin: F::Context,
+}
+
+/// a function of a `FunctionRegistry`, either one of the builtin functions `F` or a registered closure
+#[derive(Debug, Clone)]
+pub enum RegistryFunction<F, const LANES: usize>
+where
+    F: Function<F, LANES>,
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    Builtin(F),
+    Registered(Arc<Registered<LANES>>),
+}
+
+/// a closure together with its name and metadata
+pub struct Registered<const LANES: usize>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    namespace: String,
+    name: String,
+    arity: Arity,
+    is_const: bool,
+    closure: Box<Closure<LANES>>,
+}
+
+impl<F, const LANES: usize> FunctionRegistry<F, LANES>
+where
+    F: Function<F, LANES>,
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    pub fn new() -> Self {
+        Self::with_builtin_context(Default::default())
+    }
+    /// creates an empty registry, `builtin` is used to resolve the functions of `F`
+    pub fn with_builtin_context(builtin: F::Context) -> Self {
+        Self {
+            functions: HashMap::new(),
+            builtin,
+        }
+    }
+    /// registers `closure` as `namespace:name`, or as `name` if `namespace` is empty.
+    /// nested namespaces are separated by `:` like in the expression
+    ///
+    /// `arity` is checked when an expression is compiled so the closure never sees
+    /// a wrong number of arguments, `is_const` allows `optimize` to evaluate calls
+    /// with constant arguments ahead of time and should be false for closures with side effects.
+    /// a function registered under an existing name replaces it
+    pub fn register(
+        &mut self,
+        namespace: &str,
+        name: &str,
+        arity: impl Into<Arity>,
+        is_const: bool,
+        closure: impl Fn(&[Value<LANES>]) -> Result<Value<LANES>, Error> + Send + Sync + 'static,
+    ) -> &mut Self {
+        let registered = Registered {
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+            arity: arity.into(),
+            is_const,
+            closure: Box::new(closure),
+        };
+        self.functions.insert(
+            (namespace.to_string(), name.to_string()),
+            Arc::new(registered),
+        );
+        self
+    }
+    /// removes the function registered as `namespace:name` and returns whether there was one
+    pub fn unregister(&mut self, namespace: &str, name: &str) -> bool {
+        self.functions
+            .remove(&(namespace.to_string(), name.to_string()))
+            .is_some()
+    }
+    pub fn contains(&self, namespace: &str, name: &str) -> bool {
+        self.functions
+            .contains_key(&(namespace.to_string(), name.to_string()))
+    }
+    pub fn builtin_context(&self) -> &F::Context {
+        &self.builtin
+    }
+}
+
+impl<F, const LANES: usize> Default for FunctionRegistry<F, LANES>
+where
+    F: Function<F, LANES>,
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F, const LANES: usize> fmt::Debug for FunctionRegistry<F, LANES>
+where
+    F: Function<F, LANES>,
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FunctionRegistry")
+            .field("functions", &self.functions.values().collect::<Vec<_>>())
+            .field("builtin", &self.builtin)
+            .finish()
+    }
+}
+
+impl<const LANES: usize> Registered<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<const LANES: usize> fmt::Debug for Registered<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Registered")
+            .field("namespace", &self.namespace)
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .field("is_const", &self.is_const)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F, const LANES: usize> Function<RegistryFunction<F, LANES>, LANES> for RegistryFunction<F, LANES>
+where
+    F: Function<F, LANES>,
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    const NAMESPACE: &'static str = F::NAMESPACE;
+    type Context = FunctionRegistry<F, LANES>;
+
+    /// only resolves the builtin functions, registered ones need the registry
+    fn from_string(namespaces: &mut Iter<&str>, identifier: &str) -> Result<Self, Error> {
+        F::from_string(namespaces, identifier).map(Self::Builtin)
+    }
+    fn resolve(
+        context: &Self::Context,
+        namespaces: &mut Iter<&str>,
+        identifier: &str,
+    ) -> Result<Self, Error> {
+        let namespace = namespaces.clone().copied().collect::<Vec<_>>().join(":");
+        match context.functions.get(&(namespace, identifier.to_string())) {
+            Some(registered) => Ok(Self::Registered(registered.clone())),
+            None => F::resolve(&context.builtin, namespaces, identifier).map(Self::Builtin),
+        }
+    }
+    fn arity(&self) -> Arity {
+        match self {
+            Self::Builtin(function) => function.arity(),
+            Self::Registered(registered) => registered.arity,
+        }
+    }
+    fn call(&self, args: &[Value<LANES>]) -> Result<Value<LANES>, Error> {
+        match self {
+            Self::Builtin(function) => function.call(args),
+            Self::Registered(registered) => {
+                if !registered.arity.contains(args.len()) {
+                    return Err(Error::InvalidArgs);
+                }
+                (registered.closure)(args)
+            }
+        }
+    }
+    fn is_const(&self) -> bool {
+        match self {
+            Self::Builtin(function) => function.is_const(),
+            Self::Registered(registered) => registered.is_const,
+        }
+    }
+    /// registered functions are the same if they are the same registration
+    fn is_same(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Builtin(function), Self::Builtin(other)) => function.is_same(other),
+            (Self::Registered(registered), Self::Registered(other)) => {
+                Arc::ptr_eq(registered, other)
+            }
+            _ => false,
+        }
+    }
+}
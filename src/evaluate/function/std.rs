@@ -14,13 +14,8 @@ use super::Function;
 fn sqrt<const LANES: usize>(values: [Value<LANES>; 1]) -> Value<LANES>
 where
     LaneCount<LANES>: SupportedLaneCount,
-{   
-    Value::Single(Single::Float(match values {
-        [Value::Single(Single::Int(v))] => (v as f64).sqrt(),
-        [Value::Single(Single::Float(v))] => v.sqrt(),
-        [Value::Single(Single::Bool(v))] => v as i64 as f64,
-        _ => todo!()
-    }))
+{
+    values[0].sqrt()
 }
 fn print<const LANES: usize>(values: [Value<LANES>; 1]) -> Value<LANES>
 where
@@ -33,25 +28,100 @@ fn log<const LANES: usize>(values: [Value<LANES>; 2]) -> Value<LANES>
 where
     LaneCount<LANES>: SupportedLaneCount,
 {
-    if let [Value::Single(lhs), Value::Single(rhs)] = values {
-        let num = lhs.as_float();
-        let base = rhs.as_float();
-        Value::Single(Single::Float(num.log(base)))
-    } else {
-        todo!()
-    }
+    values[0].log(values[1])
+}
+fn ln<const LANES: usize>(values: [Value<LANES>; 1]) -> Value<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    values[0].ln()
+}
+fn exp<const LANES: usize>(values: [Value<LANES>; 1]) -> Value<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    values[0].exp()
+}
+fn pow<const LANES: usize>(values: [Value<LANES>; 2]) -> Value<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    values[0].pow(values[1])
 }
 fn abs<const LANES: usize>(values: [Value<LANES>; 1]) -> Value<LANES>
 where
     LaneCount<LANES>: SupportedLaneCount,
 {
-    let num = values[0];
-    match num {
-        Value::Single(Single::Float(v)) => Value::Single(Single::Float(v.abs())),
-        Value::Single(Single::Int(v)) => Value::Single(Single::Int(v.abs())),
-        Value::Single(Single::Bool(v)) => num,
-        _ => panic!(),
-    }
+    values[0].abs()
+}
+fn sin<const LANES: usize>(values: [Value<LANES>; 1]) -> Value<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    values[0].sin()
+}
+fn cos<const LANES: usize>(values: [Value<LANES>; 1]) -> Value<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    values[0].cos()
+}
+fn tan<const LANES: usize>(values: [Value<LANES>; 1]) -> Value<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    values[0].tan()
+}
+fn atan2<const LANES: usize>(values: [Value<LANES>; 2]) -> Value<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    values[0].atan2(values[1])
+}
+fn min<const LANES: usize>(values: &[Value<LANES>]) -> Value<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    values[1..].iter().fold(values[0], |min, &value| min.min(value))
+}
+fn max<const LANES: usize>(values: &[Value<LANES>]) -> Value<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    values[1..].iter().fold(values[0], |max, &value| max.max(value))
+}
+fn sum<const LANES: usize>(values: &[Value<LANES>]) -> Value<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    values[1..].iter().fold(values[0], |sum, &value| sum + value)
+}
+fn mean<const LANES: usize>(values: &[Value<LANES>]) -> Value<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    sum(values) / Value::Single(Single::Float(values.len() as f64))
+}
+/// `sqrt(x^2 + y^2 + ...)` without overflowing or underflowing in between,
+/// the arguments are divided by the largest of them before they are squared
+fn hypot<const LANES: usize>(values: &[Value<LANES>]) -> Value<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    let one = Value::Single(Single::Float(1.0));
+    let magnitudes = values.iter().map(|&value| (value * one).abs());
+    // all arguments can be zero
+    let smallest = Value::Single(Single::Float(f64::MIN_POSITIVE));
+    let scale = magnitudes.clone().fold(smallest, Value::max);
+    magnitudes
+        .map(|magnitude| {
+            let scaled = magnitude / scale;
+            scaled * scaled
+        })
+        .reduce(|sum, square| sum + square)
+        .unwrap()
+        .sqrt()
+        * scale
 }
 /*
 impl<const LANES: usize> StdTest<LANES> {
@@ -90,7 +160,19 @@ Std: std;
     Sqrt: sqrt(1),
     Print: print(1); false,
     Log: log(2),
-    Abs: abs(1)
+    Ln: ln(1),
+    Exp: exp(1),
+    Pow: pow(2),
+    Abs: abs(1),
+    Sin: sin(1),
+    Cos: cos(1),
+    Tan: tan(1),
+    Atan2: atan2(2),
+    Min: min(1..),
+    Max: max(1..),
+    Sum: sum(1..),
+    Mean: mean(1..),
+    Hypot: hypot(2..)
 ]);
 
 /*
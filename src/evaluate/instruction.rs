@@ -0,0 +1,73 @@
+use std::simd::{LaneCount, Mask, SupportedLaneCount};
+
+use super::{enums::Operator, function::Function, value::Value};
+
+/// a single step of the compiled tape, evaluated by a stack machine
+///
+/// every instruction pops its operands from the top of the stack and pushes its result,
+/// the tape is in post order so the operands of an instruction are always evaluated before it.
+///
+/// a conditional is laid out as `<condition> If <then> Else <otherwise> EndIf`,
+/// branches that no lane takes are jumped over.
+/// `&&` and `||` are laid out as `<lhs> ShortCircuit <rhs> Binary`,
+/// the statements of a script as `<statement> Pop <statement> Pop ... <last statement>`.
+/// the items of a tuple are evaluated in order and all left on the stack
+#[derive(Debug, Clone)]
+pub(crate) enum Instruction<T, const LANES: usize>
+where
+    T: Function<T, LANES>,
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    Literal(Value<LANES>),
+    Variable { index: usize },
+    Unary { operator: Operator },
+    Binary { operator: Operator },
+    /// jumps to `end` if the value on top of the stack already decides the result
+    /// of the `&&` or `||` `operator` in every lane, replacing it with that result
+    ShortCircuit { operator: Operator, end: usize },
+    /// pops `addend` and `rhs` and replaces `lhs` with `lhs * rhs + addend`
+    MulAdd,
+    /// calls `function` with the top `args` values of the stack
+    Function { function: T, args: usize },
+    /// pops the condition and pushes a `Branch`, jumps to `otherwise` if no lane is true
+    If { otherwise: usize },
+    /// jumps to `end` if only the then branch was taken
+    Else { end: usize },
+    /// pops the `Branch` and blends both results if both branches were taken
+    EndIf,
+    /// copies the value on top of the stack into the variable `index`
+    Store { index: usize },
+    /// discards the value of a statement
+    Pop,
+}
+
+/// the branches of a conditional taken by the lanes of its condition
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Branch<const LANES: usize>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    Then,
+    Otherwise,
+    Both(Mask<i64, LANES>),
+}
+
+impl<T, const LANES: usize> Instruction<T, LANES>
+where
+    T: Function<T, LANES>,
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    /// the change of the stack size caused by the instruction
+    pub(crate) fn stack_effect(&self) -> isize {
+        match self {
+            Self::Literal(_) | Self::Variable { .. } => 1,
+            Self::Unary { .. } | Self::ShortCircuit { .. } | Self::Store { .. } => 0,
+            Self::Binary { .. } | Self::Pop => -1,
+            Self::MulAdd => -2,
+            Self::Function { args, .. } => 1 - *args as isize,
+            // both branches leave a value on the stack if they are blended
+            Self::If { .. } | Self::EndIf => -1,
+            Self::Else { .. } => 0,
+        }
+    }
+}
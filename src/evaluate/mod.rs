@@ -1,9 +1,13 @@
 pub mod function;
 pub mod expression;
 pub mod value;
+pub mod batch;
+pub mod program;
 
 pub(crate) mod parse_element;
 pub(crate) mod token;
 pub(crate) mod node;
+pub(crate) mod instruction;
+pub(crate) mod definition;
 pub(crate) mod enums;
 pub(crate)mod variables;
\ No newline at end of file
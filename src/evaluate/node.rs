@@ -1,14 +1,11 @@
 use std::simd::{LaneCount, SupportedLaneCount};
 
-use crate::stack::Stack;
-
 use super::{enums::Operator, function::Function, value::{single::Single, Value}};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) enum Node<T, const LANES: usize>
 where
     T: Function<T, LANES>,
-    [(); T::MAX_ARGS]:,
     LaneCount<LANES>: SupportedLaneCount
 {
     Instruction {
@@ -16,28 +13,56 @@ where
         lhs: usize,
         rhs: usize,
     },
+    Unary {
+        operator: Operator,
+        operand: usize,
+    },
     Literal(Value<LANES>),
     Variable {
         index: usize,
     },
     Function {
         function: T,
-        args: Stack<usize, { T::MAX_ARGS }>,
+        args: Vec<usize>,
+    },
+    /// `if(condition, then, otherwise)` or `condition ? then : otherwise`
+    Conditional {
+        condition: usize,
+        then: usize,
+        otherwise: usize,
+    },
+    /// a call of a function defined with `Expression::define`,
+    /// replaced by the body of the definition before the tape is emitted
+    Call {
+        definition: usize,
+        args: Vec<usize>,
+    },
+    /// `lhs * rhs + addend` with a single rounding, only created by the optimizer
+    MulAdd {
+        lhs: usize,
+        rhs: usize,
+        addend: usize,
+    },
+    /// `name = value` or `let name = value`, evaluates to `value`
+    Assign {
+        index: usize,
+        value: usize,
+    },
+    /// statements separated by `;`, evaluates to the value of the last one
+    Sequence {
+        statements: Vec<usize>,
+    },
+    /// `(a, b, ...)`, only allowed as the result of the expression
+    Tuple {
+        items: Vec<usize>,
     },
 }
 
 impl<'a, T, const LANES: usize> Node<T, LANES>
 where
     T: Function<T, LANES>,
-    [(); T::MAX_ARGS]:,
     LaneCount<LANES>: SupportedLaneCount
 {
-    pub(crate) fn weight(&self) -> i16 {
-        match self {
-            Self::Instruction { operator, .. } => operator.weight(),
-            _ => 0,
-        }
-    }
     #[inline(always)]
     pub(crate) fn as_mut_instruction_indices(&mut self) -> (&mut usize, &mut usize) {
         if let <Node<T, LANES>>::Instruction { operator, lhs, rhs } = self {
@@ -46,4 +71,40 @@ where
             panic!()
         }
     }
+    /// the indices of the operands of the node
+    pub(crate) fn children(&self) -> Vec<usize> {
+        match self {
+            Node::Instruction { lhs, rhs, .. } => vec![*lhs, *rhs],
+            Node::Unary { operand, .. } => vec![*operand],
+            Node::Literal(_) | Node::Variable { .. } => Vec::new(),
+            Node::Function { args, .. } | Node::Call { args, .. } => args.clone(),
+            Node::MulAdd { lhs, rhs, addend } => vec![*lhs, *rhs, *addend],
+            Node::Assign { value, .. } => vec![*value],
+            Node::Sequence { statements } => statements.clone(),
+            Node::Tuple { items } => items.clone(),
+            Node::Conditional {
+                condition,
+                then,
+                otherwise,
+            } => vec![*condition, *then, *otherwise],
+        }
+    }
+    /// mutable references to the indices of the operands, in the same order as `children`
+    pub(crate) fn children_mut(&mut self) -> Vec<&mut usize> {
+        match self {
+            Node::Instruction { lhs, rhs, .. } => vec![lhs, rhs],
+            Node::Unary { operand, .. } => vec![operand],
+            Node::Literal(_) | Node::Variable { .. } => Vec::new(),
+            Node::Function { args, .. } | Node::Call { args, .. } => args.iter_mut().collect(),
+            Node::MulAdd { lhs, rhs, addend } => vec![lhs, rhs, addend],
+            Node::Assign { value, .. } => vec![value],
+            Node::Sequence { statements } => statements.iter_mut().collect(),
+            Node::Tuple { items } => items.iter_mut().collect(),
+            Node::Conditional {
+                condition,
+                then,
+                otherwise,
+            } => vec![condition, then, otherwise],
+        }
+    }
 }
@@ -1,4 +1,4 @@
-use crate::{error::Error, stack::Stack};
+use crate::error::Error;
 
 use super::{
     enums::{Identifier, Literal, TokenKind},
@@ -8,12 +8,11 @@ use super::{
 };
 use std::{fmt::Debug, slice::Iter, simd::{LaneCount, SupportedLaneCount}};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) enum ParseElement<T, const LANES: usize>
 where
     T: Function<T, LANES>,
     LaneCount<LANES>: SupportedLaneCount,
-    [(); T::MAX_ARGS]:
 {
     Token(Token),
     Node(Node<T, LANES>),
@@ -23,7 +22,6 @@ impl<T, const LANES: usize> ParseElement<T, LANES>
 where
     T: Function<T, LANES>,
     LaneCount<LANES>: SupportedLaneCount,
-    [(); T::MAX_ARGS]:
 {
     pub(crate) fn get_operands_indices(&self) -> Option<(usize, usize)> {
         if let Self::Node( Node::Instruction{lhs, rhs, ..}) = self {
@@ -36,11 +34,21 @@ where
         if let Self::Token(token) = self {
             Ok(match token.kind() {
                 TokenKind::Literal(Literal::Float) => {
-                    let value = Value::Single(Single::Float(token.slice(string).parse::<f64>()?));
+                    let value = Value::Single(Single::Float(
+                        token
+                            .slice(string)
+                            .parse::<f64>()
+                            .map_err(|_| Error::InvalidToken(token.span()))?,
+                    ));
                     Self::Node(Node::Literal(value))
                 }
                 TokenKind::Literal(Literal::Int) => {
-                    let value = Value::Single(Single::Int(token.slice(string).parse::<i64>()?));
+                    let value = Value::Single(Single::Int(
+                        token
+                            .slice(string)
+                            .parse::<i64>()
+                            .map_err(|_| Error::InvalidToken(token.span()))?,
+                    ));
                     Self::Node(Node::Literal(value))
                 }
                 TokenKind::Operator(o) => Self::Node(Node::Instruction {
@@ -53,16 +61,17 @@ where
                 }
                 TokenKind::Identifier(Identifier::Function) => {
                     let function =
-                        <T as Function<T, LANES>>::from_string(namespaces, token.slice(string))?;
+                        <T as Function<T, LANES>>::from_string(namespaces, token.slice(string))
+                            .map_err(|e| e.with_span(token.span()))?;
                     Self::Node(Node::Function {
                         function,
-                        args: Stack::new(),
+                        args: Vec::new(),
                     })
                 }
                 _ => unreachable!(),
             })
         } else {
-            Err(Error::InvalidToken)
+            Err(Error::InvalidIndex)
         }
     }
 }
@@ -0,0 +1,257 @@
+use std::{
+    fmt::Debug,
+    simd::{LaneCount, SupportedLaneCount},
+};
+
+use crate::error::Error;
+
+use super::{
+    enums::Operator,
+    function::Function,
+    instruction::{Branch, Instruction},
+    value::Value,
+    variables::Variables,
+};
+
+/// an immutable compiled expression created by `Expression::program`
+///
+/// a `Program` never changes while it is evaluated, the variable values and the scratch space
+/// live in a `Frame` instead. it is `Send + Sync` as long as the functions are,
+/// so it can be put into an `Arc` and evaluated from many threads, each with its own `Frame`.
+#[derive(Debug, Clone)]
+pub struct Program<T, const LANES: usize>
+where
+    T: Function<T, LANES>,
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    instructions: Vec<Instruction<T, LANES>>,
+    variables: Variables<LANES>,
+    stack_size: usize,
+    results: usize,
+}
+
+/// the variable values and the scratch stacks used to evaluate a `Program`
+#[derive(Debug, Clone)]
+pub struct Frame<const LANES: usize>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    values: Vec<Value<LANES>>,
+    stack: Vec<Value<LANES>>,
+    control: Vec<Branch<LANES>>,
+}
+
+impl<T, const LANES: usize> Program<T, LANES>
+where
+    T: Function<T, LANES> + Clone + Debug,
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    pub(crate) fn new(
+        instructions: Vec<Instruction<T, LANES>>,
+        variables: Variables<LANES>,
+        stack_size: usize,
+        results: usize,
+    ) -> Self {
+        Self {
+            instructions,
+            variables,
+            stack_size,
+            results,
+        }
+    }
+    /// creates a new `Frame` holding the values the variables had when the program was created
+    pub fn frame(&self) -> Frame<LANES> {
+        let mut frame = Frame::with_capacity(self.stack_size);
+        frame.load(self.variables.values());
+        frame
+    }
+    /// returns the index of the variable with the name `identifier`
+    pub fn variable_index(&self, identifier: &str) -> Result<usize, Error> {
+        self.variables.index_of(identifier)
+    }
+    pub fn variables_iter(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.variables.variables()
+    }
+    /// the number of variables a `Frame` for this program holds
+    pub fn variable_count(&self) -> usize {
+        self.variables.values().len()
+    }
+    /// the number of variables that aren't assigned by the script,
+    /// they come before the assigned ones
+    pub fn input_count(&self) -> usize {
+        self.variables.inputs()
+    }
+    /// the number of values the program evaluates to, more than one for a tuple
+    pub fn result_count(&self) -> usize {
+        self.results
+    }
+    /// evaluates the program with the variable values of `frame`,
+    /// the variables assigned by the script are written back into it
+    #[inline]
+    pub fn eval(&self, frame: &mut Frame<LANES>) -> Result<Value<LANES>, Error> {
+        if self.results != 1 {
+            return Err(Error::ResultCount);
+        }
+        self.run(frame)?;
+        frame.stack.pop().ok_or(Error::InvalidIndex)
+    }
+    /// evaluates a program returning a tuple and writes its items into `results`
+    #[inline]
+    pub fn eval_tuple(
+        &self,
+        frame: &mut Frame<LANES>,
+        results: &mut [Value<LANES>],
+    ) -> Result<(), Error> {
+        if results.len() != self.results {
+            return Err(Error::ResultCount);
+        }
+        self.run(frame)?;
+        results.copy_from_slice(frame.results());
+        Ok(())
+    }
+    /// runs the program and leaves the results on the stack of `frame`
+    #[inline]
+    pub(crate) fn run(&self, frame: &mut Frame<LANES>) -> Result<(), Error> {
+        if frame.values.len() != self.variable_count() {
+            return Err(Error::InvalidArgs);
+        }
+        frame.run(&self.instructions)
+    }
+}
+
+impl<const LANES: usize> Frame<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    /// creates a frame without variables with room for `stack_size` values on the stack
+    pub(crate) fn with_capacity(stack_size: usize) -> Self {
+        Self {
+            values: Vec::new(),
+            stack: Vec::with_capacity(stack_size),
+            control: Vec::new(),
+        }
+    }
+    /// replaces the variable values with `values`, reusing the allocation
+    pub(crate) fn load(&mut self, values: &[Value<LANES>]) {
+        self.values.clear();
+        self.values.extend_from_slice(values);
+    }
+    /// runs `instructions` on the values and stacks of the frame
+    #[inline]
+    pub(crate) fn run<T: Function<T, LANES>>(
+        &mut self,
+        instructions: &[Instruction<T, LANES>],
+    ) -> Result<(), Error> {
+        run(instructions, &mut self.values, &mut self.stack, &mut self.control)
+    }
+    /// the variable values, including the ones assigned by the last `run`
+    pub(crate) fn values(&self) -> &[Value<LANES>] {
+        &self.values
+    }
+    pub fn set_variable_by_index(&mut self, index: usize, value: Value<LANES>) -> Result<(), Error> {
+        *self.values.get_mut(index).ok_or(Error::InvalidVariable)? = value;
+        Ok(())
+    }
+    pub fn variable(&self, index: usize) -> Result<Value<LANES>, Error> {
+        self.values.get(index).copied().ok_or(Error::InvalidVariable)
+    }
+    /// the results of the last `Program::run`
+    pub(crate) fn results(&self) -> &[Value<LANES>] {
+        &self.stack
+    }
+}
+
+/// runs an instruction tape on `stack` and leaves the results on it,
+/// `control` keeps track of the branches taken by the conditionals being evaluated
+#[inline]
+pub(crate) fn run<T, const LANES: usize>(
+    instructions: &[Instruction<T, LANES>],
+    variables: &mut [Value<LANES>],
+    stack: &mut Vec<Value<LANES>>,
+    control: &mut Vec<Branch<LANES>>,
+) -> Result<(), Error>
+where
+    T: Function<T, LANES>,
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    stack.clear();
+    control.clear();
+    let mut counter = 0;
+    while let Some(instruction) = instructions.get(counter) {
+        counter += 1;
+        match instruction {
+            Instruction::Literal(value) => stack.push(*value),
+            Instruction::Variable { index } => stack.push(variables[*index]),
+            Instruction::Unary { operator } => {
+                let operand = stack.last_mut().ok_or(Error::InvalidIndex)?;
+                *operand = operator.eval_unary(*operand);
+            }
+            Instruction::Binary { operator } => {
+                let rhs = stack.pop().ok_or(Error::InvalidIndex)?;
+                let lhs = stack.last_mut().ok_or(Error::InvalidIndex)?;
+                *lhs = operator.eval(*lhs, rhs);
+            }
+            Instruction::MulAdd => {
+                let addend = stack.pop().ok_or(Error::InvalidIndex)?;
+                let rhs = stack.pop().ok_or(Error::InvalidIndex)?;
+                let lhs = stack.last_mut().ok_or(Error::InvalidIndex)?;
+                *lhs = lhs.mul_add(rhs, addend);
+            }
+            Instruction::ShortCircuit { operator, end } => {
+                let lhs = stack.last_mut().ok_or(Error::InvalidIndex)?;
+                // `false && x` is false and `true || x` is true
+                let decides = *operator == Operator::Or;
+                if lhs.all(decides) {
+                    *lhs = lhs.as_bool();
+                    counter = *end;
+                }
+            }
+            Instruction::Function { function, args } => {
+                let start = stack.len() - args;
+                let value = function.call(&stack[start..])?;
+                stack.truncate(start);
+                stack.push(value);
+            }
+            Instruction::If { otherwise } => {
+                let branch = match stack.pop().ok_or(Error::InvalidIndex)? {
+                    Value::Single(condition) if condition.as_bool() => Branch::Then,
+                    Value::Single(_) => Branch::Otherwise,
+                    Value::Simd(condition) => {
+                        let mask = condition.as_mask();
+                        if mask.all() {
+                            Branch::Then
+                        } else if !mask.any() {
+                            Branch::Otherwise
+                        } else {
+                            Branch::Both(mask)
+                        }
+                    }
+                };
+                if let Branch::Otherwise = branch {
+                    counter = *otherwise;
+                }
+                control.push(branch);
+            }
+            Instruction::Else { end } => {
+                if let Some(Branch::Then) = control.last() {
+                    control.pop();
+                    counter = *end;
+                }
+            }
+            Instruction::EndIf => {
+                if let Some(Branch::Both(mask)) = control.pop() {
+                    let otherwise = stack.pop().ok_or(Error::InvalidIndex)?;
+                    let then = stack.last_mut().ok_or(Error::InvalidIndex)?;
+                    *then = Value::select(mask, *then, otherwise);
+                }
+            }
+            Instruction::Store { index } => {
+                variables[*index] = *stack.last().ok_or(Error::InvalidIndex)?;
+            }
+            Instruction::Pop => {
+                stack.pop().ok_or(Error::InvalidIndex)?;
+            }
+        }
+    }
+    Ok(())
+}
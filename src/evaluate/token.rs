@@ -1,4 +1,5 @@
 use super::enums::{TokenKind, Special};
+use crate::error::Span;
 use std::ops::RangeInclusive;
 
 #[derive(Debug, Clone)]
@@ -30,12 +31,12 @@ impl<'a> Token {
         self.token_kind = token_kind;
         self.end += 1;
     }
-    pub(crate) fn new_neg_zero() -> Self {
-        Self {
-            token_kind: TokenKind::Special(Special::NegZero),
-            start: 0,
-            end: 0,
-        }
+    /// moves the end of the token to the byte index `end`, used for multibyte characters
+    pub(crate) fn set_end(&mut self, end: usize) {
+        self.end = end;
+    }
+    pub(crate) fn span(&self) -> Span {
+        Span::new(self.start, self.end)
     }
     pub(crate) fn slice<'b>(&'a self, string: &'b str) -> &'b str {
         &string[self.start..self.end]
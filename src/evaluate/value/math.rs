@@ -0,0 +1,344 @@
+//! vectorized floating point functions on `simd::Simd<f64, LANES>`
+//!
+//! every function works on all lanes at once and never branches on the value of a single lane,
+//! unlike calling the scalar `f64` methods once per lane.
+//! the accuracy of each function is given relative to the matching `f64` method,
+//! and is checked in the tests.
+#![allow(clippy::excessive_precision)]
+
+use std::simd::{
+    self,
+    cmp::{SimdPartialEq, SimdPartialOrd},
+    num::{SimdFloat, SimdInt, SimdUint},
+    LaneCount, StdFloat, SupportedLaneCount,
+};
+
+type Vector<const LANES: usize> = simd::Simd<f64, LANES>;
+
+// ln(2) split into a part with the lower bits zeroed, so multiplying it by an exponent is exact,
+// and the remainder
+const LN_2_HI: f64 = 6.93147180369123816490e-01;
+const LN_2_LO: f64 = 1.90821492927058770002e-10;
+// pi/2 split into three parts with enough trailing zeros that `k * part` is exact for |k| < 2^29
+const FRAC_PI_2_1: f64 = 1.5707962512969970703125;
+const FRAC_PI_2_2: f64 = 7.54978941586159635335e-8;
+const FRAC_PI_2_3: f64 = 5.39030285815811905290e-15;
+// the part of pi/2 that doesn't fit into a f64
+const FRAC_PI_2_LO: f64 = 6.123233995736765886130e-17;
+
+/// taylor series of `e^r` from the 13th to the 0th coefficient
+const EXP: [f64; 14] = [
+    1.6059043836821613e-10,
+    2.08767569878681e-09,
+    2.505210838544172e-08,
+    2.755731922398589e-07,
+    2.7557319223985893e-06,
+    2.48015873015873e-05,
+    0.0001984126984126984,
+    0.001388888888888889,
+    0.008333333333333333,
+    0.041666666666666664,
+    0.16666666666666666,
+    0.5,
+    1.0,
+    1.0,
+];
+/// series of `2 atanh(s) / s - 2` in `s^2`, which is `2/23 s^22 + ... + 2/3 s^2`
+const LN: [f64; 11] = [
+    0.08695652173913043,
+    0.09523809523809523,
+    0.10526315789473684,
+    0.11764705882352941,
+    0.13333333333333333,
+    0.15384615384615385,
+    0.18181818181818182,
+    0.2222222222222222,
+    0.2857142857142857,
+    0.4,
+    0.6666666666666666,
+];
+/// taylor series of `(sin(r) - r) / r^3` in `r^2`
+const SIN: [f64; 7] = [
+    -7.647163731819816e-13,
+    1.6059043836821613e-10,
+    -2.505210838544172e-08,
+    2.7557319223985893e-06,
+    -0.0001984126984126984,
+    0.008333333333333333,
+    -0.16666666666666666,
+];
+/// taylor series of `(cos(r) - 1 + r^2/2) / r^4` in `r^2`
+const COS: [f64; 7] = [
+    4.779477332387385e-14,
+    -1.1470745597729725e-11,
+    2.08767569878681e-09,
+    -2.755731922398589e-07,
+    2.48015873015873e-05,
+    -0.001388888888888889,
+    0.041666666666666664,
+];
+/// rational approximation of `(atan(t) - t) / t^3` in `t^2` for |t| <= 0.66, from cephes
+const ATAN_P: [f64; 5] = [
+    -8.750608600031904122785e-1,
+    -1.615753718733365076637e1,
+    -7.500855792314704667340e1,
+    -1.228866684490136173410e2,
+    -6.485021904942025371773e1,
+];
+const ATAN_Q: [f64; 6] = [
+    1.0,
+    2.485846490142306297962e1,
+    1.650270098316988542046e2,
+    4.328810604912902668951e2,
+    4.853903996359136964868e2,
+    1.945506571482613964425e2,
+];
+
+#[inline(always)]
+fn splat<const LANES: usize>(value: f64) -> Vector<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    Vector::splat(value)
+}
+
+/// evaluates a polynomial with its coefficients ordered from the highest to the lowest degree
+#[inline(always)]
+fn polynomial<const LANES: usize, const N: usize>(
+    x: Vector<LANES>,
+    coefficients: [f64; N],
+) -> Vector<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    let mut result = splat(coefficients[0]);
+    for coefficient in &coefficients[1..] {
+        result = result * x + splat(*coefficient);
+    }
+    result
+}
+
+/// `2^k` for every lane, `k` has to be in `-1022..=1023`
+#[inline(always)]
+fn pow2<const LANES: usize>(k: simd::Simd<i64, LANES>) -> Vector<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    Vector::from_bits(((k + simd::Simd::splat(1023)) << simd::Simd::splat(52)).cast())
+}
+
+/// `e^x`, within 1 ulp of `f64::exp`
+pub(crate) fn exp<const LANES: usize>(x: Vector<LANES>) -> Vector<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    // x = k ln(2) + r with |r| <= ln(2)/2
+    let k = (x * splat(std::f64::consts::LOG2_E)).round();
+    let r = (x - k * splat(LN_2_HI)) - k * splat(LN_2_LO);
+    let result = polynomial(r, EXP);
+    // scale in two steps so results in the subnormal range aren't flushed to zero
+    let k = k
+        .simd_clamp(splat(-2000.0), splat(2000.0))
+        .cast::<i64>();
+    let half = k / simd::Simd::splat(2);
+    let result = result * pow2(half) * pow2(k - half);
+    let result = x
+        .simd_gt(splat(709.782712893384))
+        .select(splat(f64::INFINITY), result);
+    let result = x.simd_lt(splat(-745.1332191019412)).select(splat(0.0), result);
+    x.is_nan().select(x, result)
+}
+
+/// the natural logarithm, within 1 ulp of `f64::ln`
+pub(crate) fn ln<const LANES: usize>(x: Vector<LANES>) -> Vector<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    // move subnormals into the normal range so their exponent can be read from the bits
+    let subnormal = x.simd_lt(splat(f64::MIN_POSITIVE));
+    let scaled = subnormal.select(x * splat(18014398509481984.0), x);
+    let bits = scaled.to_bits();
+    let exponent = (bits >> simd::Simd::splat(52)).cast::<i64>()
+        - subnormal.select(simd::Simd::splat(1023 + 54), simd::Simd::splat(1023));
+    // x = 2^exponent * m with m in [sqrt(2)/2, sqrt(2))
+    let m = Vector::from_bits(
+        (bits & simd::Simd::splat(0x000f_ffff_ffff_ffff)) | simd::Simd::splat(0x3ff0_0000_0000_0000),
+    );
+    let big = m.simd_gt(splat(std::f64::consts::SQRT_2));
+    let m = big.select(m * splat(0.5), m);
+    let exponent = big
+        .select(exponent + simd::Simd::splat(1), exponent)
+        .cast::<f64>();
+    // ln(1 + f) = 2 atanh(s) with s = f / (2 + f), rearranged so the exact f dominates the sum
+    let f = m - splat(1.0);
+    let s = f / (splat(2.0) + f);
+    let z = s * s;
+    let r = z * polynomial(z, LN);
+    let half_square = splat(0.5) * f * f;
+    let result = exponent * splat(LN_2_HI)
+        - ((half_square - (s * (half_square + r) + exponent * splat(LN_2_LO))) - f);
+    let result = x.simd_eq(splat(0.0)).select(splat(f64::NEG_INFINITY), result);
+    let result = x
+        .simd_eq(splat(f64::INFINITY))
+        .select(splat(f64::INFINITY), result);
+    (x.simd_lt(splat(0.0)) | x.is_nan()).select(splat(f64::NAN), result)
+}
+
+/// `x^n` by repeated squaring like `f64::powi`, `n` has to be an integer in every lane
+#[inline(always)]
+fn powi<const LANES: usize>(x: Vector<LANES>, n: Vector<LANES>) -> Vector<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    let mut exponent = n.abs().cast::<i64>();
+    let mut base = x;
+    let mut result = splat(1.0);
+    while exponent.simd_ne(simd::Simd::splat(0)).any() {
+        let odd = (exponent & simd::Simd::splat(1)).simd_eq(simd::Simd::splat(1));
+        result = odd.select(result * base, result);
+        base = base * base;
+        exponent >>= simd::Simd::splat(1);
+    }
+    n.is_sign_negative().select(splat(1.0) / result, result)
+}
+
+/// `x^y` with the special cases of `f64::powf`
+///
+/// integer exponents up to 1024 are computed like `powi` with an error of at most about `|y|` ulp,
+/// other exponents are computed as `e^(y ln(x))` whose relative error is about `2 + |y ln(x)|` ulp
+pub(crate) fn pow<const LANES: usize>(x: Vector<LANES>, y: Vector<LANES>) -> Vector<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    let integer = y.fract().simd_eq(splat(0.0));
+    let small = integer & y.abs().simd_le(splat(1024.0));
+    let small_result = powi(x, small.select(y, splat(0.0)));
+    if small.all() {
+        return small_result;
+    }
+    let result = exp(y * ln(x.abs()));
+    let odd = integer & (y * splat(0.5)).fract().simd_ne(splat(0.0));
+    let negative = x.is_sign_negative() & !x.is_nan();
+    let result = (negative & odd).select(-result, result);
+    let result = (negative & !integer & y.is_finite() & x.is_finite() & x.simd_ne(splat(0.0)))
+        .select(splat(f64::NAN), result);
+    let one = y.simd_eq(splat(0.0))
+        | x.simd_eq(splat(1.0))
+        | (x.simd_eq(splat(-1.0)) & y.is_infinite());
+    small.select(small_result, one.select(splat(1.0), result))
+}
+
+/// reduces `x` to `r` in [-pi/4, pi/4] and the quadrant `k` so that `x = k pi/2 + r`
+///
+/// exact for |x| < 2^29 pi/2, larger arguments lose accuracy
+#[inline(always)]
+fn reduce<const LANES: usize>(x: Vector<LANES>) -> (Vector<LANES>, simd::Simd<i64, LANES>)
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    let k = (x * splat(std::f64::consts::FRAC_2_PI)).round();
+    let r = ((x - k * splat(FRAC_PI_2_1)) - k * splat(FRAC_PI_2_2)) - k * splat(FRAC_PI_2_3);
+    (r, k.cast::<i64>() & simd::Simd::splat(3))
+}
+#[inline(always)]
+fn sin_reduced<const LANES: usize>(r: Vector<LANES>) -> Vector<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    let z = r * r;
+    r + r * z * polynomial(z, SIN)
+}
+#[inline(always)]
+fn cos_reduced<const LANES: usize>(r: Vector<LANES>) -> Vector<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    let z = r * r;
+    splat(1.0) - z * splat(0.5) + z * z * polynomial(z, COS)
+}
+
+/// the sine, within 1 ulp of `f64::sin` for |x| < 1e5
+pub(crate) fn sin<const LANES: usize>(x: Vector<LANES>) -> Vector<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    let (r, quadrant) = reduce(x);
+    let odd = (quadrant & simd::Simd::splat(1)).simd_eq(simd::Simd::splat(1));
+    let result = odd.select(cos_reduced(r), sin_reduced(r));
+    quadrant.simd_ge(simd::Simd::splat(2)).select(-result, result)
+}
+
+/// the cosine, within 1 ulp of `f64::cos` for |x| < 1e5
+pub(crate) fn cos<const LANES: usize>(x: Vector<LANES>) -> Vector<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    let (r, quadrant) = reduce(x);
+    let odd = (quadrant & simd::Simd::splat(1)).simd_eq(simd::Simd::splat(1));
+    let result = odd.select(sin_reduced(r), cos_reduced(r));
+    let negative = quadrant.simd_eq(simd::Simd::splat(1)) | quadrant.simd_eq(simd::Simd::splat(2));
+    negative.select(-result, result)
+}
+
+/// the tangent, within 4 ulp of `f64::tan` for |x| < 1e5
+pub(crate) fn tan<const LANES: usize>(x: Vector<LANES>) -> Vector<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    let (r, quadrant) = reduce(x);
+    let sin = sin_reduced(r);
+    let cos = cos_reduced(r);
+    let odd = (quadrant & simd::Simd::splat(1)).simd_eq(simd::Simd::splat(1));
+    odd.select(-cos / sin, sin / cos)
+}
+
+/// the arc tangent of `t` in [0, 1]
+#[inline(always)]
+fn atan_unit<const LANES: usize>(t: Vector<LANES>) -> Vector<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    // atan(t) = pi/4 + atan((t - 1) / (t + 1)) keeps the argument of the approximation small
+    let big = t.simd_gt(splat(0.66));
+    let x = big.select((t - splat(1.0)) / (t + splat(1.0)), t);
+    let z = x * x;
+    let result = x + x * z * polynomial(z, ATAN_P) / polynomial(z, ATAN_Q);
+    big.select(
+        splat(std::f64::consts::FRAC_PI_4) + (result + splat(0.5 * FRAC_PI_2_LO)),
+        result,
+    )
+}
+
+/// the four quadrant arc tangent of `y / x`, within 2 ulp of `f64::atan2`
+pub(crate) fn atan2<const LANES: usize>(y: Vector<LANES>, x: Vector<LANES>) -> Vector<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    let ax = x.abs();
+    let ay = y.abs();
+    let max = ax.simd_max(ay);
+    let min = ax.simd_min(ay);
+    // min / max is NaN if both are zero or infinite
+    let t = max.simd_eq(splat(0.0)).select(splat(0.0), min / max);
+    let t = (ax.is_infinite() & ay.is_infinite()).select(splat(1.0), t);
+    let result = atan_unit(t);
+    let result = ay.simd_gt(ax).select(
+        splat(std::f64::consts::FRAC_PI_2) - result + splat(FRAC_PI_2_LO),
+        result,
+    );
+    let result = x.is_sign_negative().select(
+        splat(std::f64::consts::PI) - result + splat(2.0 * FRAC_PI_2_LO),
+        result,
+    );
+    let result = result.copysign(y);
+    (x.is_nan() | y.is_nan()).select(splat(f64::NAN), result)
+}
+
+/// the square root, exact like `f64::sqrt`
+#[inline(always)]
+pub(crate) fn sqrt<const LANES: usize>(x: Vector<LANES>) -> Vector<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    x.sqrt()
+}
@@ -1,10 +1,13 @@
 use std::{
-    ops::{Add, Div, Mul, Rem, Sub},
-    simd::{LaneCount, SupportedLaneCount},
+    ops::{Add, Div, Mul, Neg, Rem, Sub},
+    simd::{self as std_simd, num::SimdFloat, num::SimdInt, LaneCount, SupportedLaneCount},
 };
 
 use self::{simd::Simd, single::Single};
 
+use super::enums::Literal;
+
+pub(crate) mod math;
 pub mod simd;
 pub mod single;
 
@@ -32,11 +35,10 @@ impl<const LANES: usize> Value<LANES>
 where
     LaneCount<LANES>: SupportedLaneCount,
 {
+    /// broadcasts a `Single` across all lanes, keeping its type
     pub fn to_simd(&mut self) {
         if let Value::Single(single) = self {
-            let value = single.as_float();
-            let array = [value; LANES];
-            *self = Value::Simd(Simd::Float(array.into()));
+            *self = Value::Simd(Simd::splat(*single));
         }
     }
     pub fn as_single_float(self) -> f64 {
@@ -54,12 +56,7 @@ where
     type Output = Value<LANES>;
 
     fn add(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Value::Single(lhs), Value::Simd(rhs)) => todo!(),
-            (Value::Simd(lhs), Value::Single(rhs)) => todo!(),
-            (Value::Simd(lhs), Value::Simd(rhs)) => Value::Simd(lhs + rhs),
-            (Value::Single(lhs), Value::Single(rhs)) => Value::Single(lhs + rhs),
-        }
+        self.binary(rhs, Single::add, Simd::add)
     }
 }
 impl<const LANES: usize> Sub for Value<LANES>
@@ -69,12 +66,7 @@ where
     type Output = Value<LANES>;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Value::Single(lhs), Value::Simd(rhs)) => todo!(),
-            (Value::Simd(lhs), Value::Single(rhs)) => todo!(),
-            (Value::Simd(lhs), Value::Simd(rhs)) => Value::Simd(lhs - rhs),
-            (Value::Single(lhs), Value::Single(rhs)) => Value::Single(lhs - rhs),
-        }
+        self.binary(rhs, Single::sub, Simd::sub)
     }
 }
 impl<const LANES: usize> Mul for Value<LANES>
@@ -84,12 +76,7 @@ where
     type Output = Value<LANES>;
 
     fn mul(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Value::Single(lhs), Value::Simd(rhs)) => todo!(),
-            (Value::Simd(lhs), Value::Single(rhs)) => todo!(),
-            (Value::Simd(lhs), Value::Simd(rhs)) => Value::Simd(lhs * rhs),
-            (Value::Single(lhs), Value::Single(rhs)) => Value::Single(lhs * rhs),
-        }
+        self.binary(rhs, Single::mul, Simd::mul)
     }
 }
 impl<const LANES: usize> Div for Value<LANES>
@@ -99,12 +86,7 @@ where
     type Output = Value<LANES>;
 
     fn div(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Value::Single(lhs), Value::Simd(rhs)) => todo!(),
-            (Value::Simd(lhs), Value::Single(rhs)) => todo!(),
-            (Value::Simd(lhs), Value::Simd(rhs)) => Value::Simd(lhs / rhs),
-            (Value::Single(lhs), Value::Single(rhs)) => Value::Single(lhs / rhs),
-        }
+        self.binary(rhs, Single::div, Simd::div)
     }
 }
 impl<const LANES: usize> Rem for Value<LANES>
@@ -114,11 +96,19 @@ where
     type Output = Value<LANES>;
 
     fn rem(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Value::Single(lhs), Value::Simd(rhs)) => todo!(),
-            (Value::Simd(lhs), Value::Single(rhs)) => todo!(),
-            (Value::Simd(lhs), Value::Simd(rhs)) => Value::Simd(lhs % rhs),
-            (Value::Single(lhs), Value::Single(rhs)) => Value::Single(lhs % rhs),
+        self.binary(rhs, Single::rem, Simd::rem)
+    }
+}
+impl<const LANES: usize> Neg for Value<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    type Output = Value<LANES>;
+
+    fn neg(self) -> Self::Output {
+        match self {
+            Value::Simd(v) => Value::Simd(-v),
+            Value::Single(v) => Value::Single(-v),
         }
     }
 }
@@ -128,11 +118,182 @@ where
     LaneCount<LANES>: SupportedLaneCount,
 {
     pub(crate) fn pow(self, rhs: Self) -> Self {
+        self.binary(rhs, Single::pow, Simd::pow)
+    }
+    /// `self * a + b`, fused into a single rounding if any operand is a float
+    pub(crate) fn mul_add(self, a: Self, b: Self) -> Self {
+        match (self, a, b) {
+            (Value::Single(value), Value::Single(a), Value::Single(b)) => {
+                Value::Single(value.mul_add(a, b))
+            }
+            _ => {
+                let splat = |value| match value {
+                    Value::Simd(value) => value,
+                    Value::Single(value) => Simd::splat(value),
+                };
+                Value::Simd(splat(self).mul_add(splat(a), splat(b)))
+            }
+        }
+    }
+    /// the type of the lanes of the value
+    pub(crate) fn kind(self) -> Literal {
+        match self {
+            Value::Single(Single::Int(_)) | Value::Simd(Simd::Int(_)) => Literal::Int,
+            Value::Single(Single::Float(_)) | Value::Simd(Simd::Float(_)) => Literal::Float,
+            Value::Single(Single::Bool(_)) | Value::Simd(Simd::Bool(_)) => Literal::Bool,
+        }
+    }
+    /// converts every lane to a bool
+    pub(crate) fn as_bool(self) -> Self {
+        match self {
+            Value::Simd(value) => Value::Simd(Simd::Bool(value.as_mask())),
+            Value::Single(value) => Value::Single(Single::Bool(value.as_bool())),
+        }
+    }
+    /// returns true if every lane converted to a bool is `value`
+    pub(crate) fn all(self, value: bool) -> bool {
+        match self {
+            Value::Simd(simd) => {
+                let mask = simd.as_mask();
+                if value {
+                    mask.all()
+                } else {
+                    !mask.any()
+                }
+            }
+            Value::Single(single) => single.as_bool() == value,
+        }
+    }
+    /// blends `then` and `otherwise` lane by lane, see `Simd::select`
+    pub(crate) fn select(mask: std_simd::Mask<i64, LANES>, then: Self, otherwise: Self) -> Self {
+        let splat = |value| match value {
+            Value::Simd(value) => value,
+            Value::Single(value) => Simd::splat(value),
+        };
+        Value::Simd(Simd::select(mask, splat(then), splat(otherwise)))
+    }
+    /// applies `single` or `simd` depending on the kind of both operands,
+    /// a `Single` operand mixed with a `Simd` operand is broadcast across all lanes first
+    #[inline(always)]
+    fn binary(
+        self,
+        rhs: Self,
+        single: impl Fn(Single, Single) -> Single,
+        simd: impl Fn(Simd<LANES>, Simd<LANES>) -> Simd<LANES>,
+    ) -> Self {
         match (self, rhs) {
-            (Value::Single(lhs), Value::Simd(rhs)) => todo!(),
-            (Value::Simd(lhs), Value::Single(rhs)) => todo!(),
-            (Value::Simd(lhs), Value::Simd(rhs)) => Value::Simd(lhs.pow(rhs)),
-            (Value::Single(lhs), Value::Single(rhs)) => Value::Single(lhs.pow(rhs)),
+            (Value::Single(lhs), Value::Simd(rhs)) => Value::Simd(simd(Simd::splat(lhs), rhs)),
+            (Value::Simd(lhs), Value::Single(rhs)) => Value::Simd(simd(lhs, Simd::splat(rhs))),
+            (Value::Simd(lhs), Value::Simd(rhs)) => Value::Simd(simd(lhs, rhs)),
+            (Value::Single(lhs), Value::Single(rhs)) => Value::Single(single(lhs, rhs)),
+        }
+    }
+    pub(crate) fn equal(self, rhs: Self) -> Self {
+        self.binary(rhs, Single::equal, Simd::equal)
+    }
+    pub(crate) fn not_equal(self, rhs: Self) -> Self {
+        self.binary(rhs, Single::not_equal, Simd::not_equal)
+    }
+    pub(crate) fn greater(self, rhs: Self) -> Self {
+        self.binary(rhs, Single::greater, Simd::greater)
+    }
+    pub(crate) fn greater_equal(self, rhs: Self) -> Self {
+        self.binary(rhs, Single::greater_equal, Simd::greater_equal)
+    }
+    pub(crate) fn smaller(self, rhs: Self) -> Self {
+        self.binary(rhs, Single::smaller, Simd::smaller)
+    }
+    pub(crate) fn smaller_equal(self, rhs: Self) -> Self {
+        self.binary(rhs, Single::smaller_equal, Simd::smaller_equal)
+    }
+    pub(crate) fn and(self, rhs: Self) -> Self {
+        self.binary(rhs, Single::and, Simd::and)
+    }
+    pub(crate) fn or(self, rhs: Self) -> Self {
+        self.binary(rhs, Single::or, Simd::or)
+    }
+    pub(crate) fn xor(self, rhs: Self) -> Self {
+        self.binary(rhs, Single::xor, Simd::xor)
+    }
+    pub(crate) fn not(self) -> Self {
+        match self {
+            Value::Simd(v) => Value::Simd(v.not()),
+            Value::Single(v) => Value::Single(v.not()),
+        }
+    }
+}
+
+/// floating point functions, ints and bools are converted to floats first
+impl<const LANES: usize> Value<LANES>
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    #[inline(always)]
+    fn float_unary(
+        self,
+        single: impl Fn(f64) -> f64,
+        simd: impl Fn(std_simd::Simd<f64, LANES>) -> std_simd::Simd<f64, LANES>,
+    ) -> Self {
+        match self {
+            Value::Simd(v) => Value::Simd(Simd::Float(simd(v.as_float()))),
+            Value::Single(v) => Value::Single(Single::Float(single(v.as_float()))),
+        }
+    }
+    #[inline(always)]
+    fn float_binary(
+        self,
+        rhs: Self,
+        single: impl Fn(f64, f64) -> f64,
+        simd: impl Fn(std_simd::Simd<f64, LANES>, std_simd::Simd<f64, LANES>) -> std_simd::Simd<f64, LANES>,
+    ) -> Self {
+        self.binary(
+            rhs,
+            |lhs, rhs| Single::Float(single(lhs.as_float(), rhs.as_float())),
+            |lhs, rhs| Simd::Float(simd(lhs.as_float(), rhs.as_float())),
+        )
+    }
+    pub fn sqrt(self) -> Self {
+        self.float_unary(f64::sqrt, math::sqrt)
+    }
+    pub fn exp(self) -> Self {
+        self.float_unary(f64::exp, math::exp)
+    }
+    pub fn ln(self) -> Self {
+        self.float_unary(f64::ln, math::ln)
+    }
+    /// the logarithm of `self` with base `base`
+    pub fn log(self, base: Self) -> Self {
+        self.float_binary(base, f64::log, |num, base| math::ln(num) / math::ln(base))
+    }
+    pub fn sin(self) -> Self {
+        self.float_unary(f64::sin, math::sin)
+    }
+    pub fn cos(self) -> Self {
+        self.float_unary(f64::cos, math::cos)
+    }
+    pub fn tan(self) -> Self {
+        self.float_unary(f64::tan, math::tan)
+    }
+    /// the four quadrant arc tangent of `self / x`
+    pub fn atan2(self, x: Self) -> Self {
+        self.float_binary(x, f64::atan2, math::atan2)
+    }
+    /// the smaller of `self` and `rhs`
+    pub fn min(self, rhs: Self) -> Self {
+        self.binary(rhs, Single::min, Simd::min)
+    }
+    /// the larger of `self` and `rhs`
+    pub fn max(self, rhs: Self) -> Self {
+        self.binary(rhs, Single::max, Simd::max)
+    }
+    /// the absolute value, ints stay ints and bools are unchanged
+    pub fn abs(self) -> Self {
+        match self {
+            Value::Single(Single::Int(v)) => Value::Single(Single::Int(v.wrapping_abs())),
+            Value::Single(Single::Float(v)) => Value::Single(Single::Float(v.abs())),
+            Value::Simd(Simd::Int(v)) => Value::Simd(Simd::Int(v.abs())),
+            Value::Simd(Simd::Float(v)) => Value::Simd(Simd::Float(v.abs())),
+            Value::Single(Single::Bool(_)) | Value::Simd(Simd::Bool(_)) => self,
         }
     }
 }
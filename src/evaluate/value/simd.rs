@@ -1,16 +1,28 @@
 use std::{
-    ops::{Add, Div, Mul, Rem, Sub},
-    simd::{self, f64x8, i64x8, LaneCount, SupportedLaneCount},
+    array,
+    ops::{Add, Div, Mul, Neg, Rem, Sub},
+    simd::{
+        self,
+        cmp::{SimdOrd, SimdPartialEq, SimdPartialOrd},
+        num::{SimdFloat, SimdInt},
+        LaneCount, Mask, StdFloat, SupportedLaneCount,
+    },
 };
 
+use super::{math, single::Single};
+
+/// a vector of values, uses the same promotion rules as `Single`
+///
+/// any operation involving a float produces floats,
+/// any other arithmetic operation produces ints
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 pub enum Simd<const LANES: usize>
 where
     LaneCount<LANES>: SupportedLaneCount,
 {
-    //Int(simd::Simd<i64, LANES>),
+    Int(simd::Simd<i64, LANES>),
     Float(simd::Simd<f64, LANES>),
-    //Bool(simd::Simd<u64, LANES>),
+    Bool(Mask<i64, LANES>),
 }
 
 impl<const LANES: usize> std::fmt::Display for Simd<LANES>
@@ -19,7 +31,9 @@ where
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         match self {
-            Self::Float(v) => write!(f, "{:#?}", v)
+            Self::Int(v) => write!(f, "{:#?}", v),
+            Self::Float(v) => write!(f, "{:#?}", v),
+            Self::Bool(v) => write!(f, "{:#?}", v),
         }
     }
 }
@@ -29,9 +43,47 @@ where
 {
     pub fn as_float(&self) -> simd::Simd<f64, LANES> {
         match self {
+            Simd::Int(v) => v.cast(),
             Simd::Float(v) => *v,
+            Simd::Bool(v) => v.select(simd::Simd::splat(1.0), simd::Simd::splat(0.0)),
+        }
+    }
+    pub fn as_int(&self) -> simd::Simd<i64, LANES> {
+        match self {
+            Simd::Int(v) => *v,
+            Simd::Float(v) => v.cast(),
+            Simd::Bool(v) => v.select(simd::Simd::splat(1), simd::Simd::splat(0)),
+        }
+    }
+    /// returns a mask with every lane set that isn't zero
+    pub fn as_mask(&self) -> Mask<i64, LANES> {
+        match self {
+            Simd::Int(v) => v.simd_ne(simd::Simd::splat(0)),
+            Simd::Float(v) => v.simd_ne(simd::Simd::splat(0.0)),
+            Simd::Bool(v) => *v,
         }
     }
+    /// broadcasts a single value across all lanes
+    pub fn splat(value: Single) -> Self {
+        match value {
+            Single::Int(v) => Simd::Int(simd::Simd::splat(v)),
+            Single::Float(v) => Simd::Float(simd::Simd::splat(v)),
+            Single::Bool(v) => Simd::Bool(Mask::splat(v)),
+        }
+    }
+    /// returns the value of a single lane
+    pub fn lane(&self, index: usize) -> Single {
+        match self {
+            Simd::Int(v) => Single::Int(v[index]),
+            Simd::Float(v) => Single::Float(v[index]),
+            Simd::Bool(v) => Single::Bool(v.test(index)),
+        }
+    }
+    /// whether the result of an operation on `self` and `rhs` is a float
+    #[inline(always)]
+    fn is_float(&self, rhs: &Self) -> bool {
+        matches!((self, rhs), (Simd::Float(_), _) | (_, Simd::Float(_)))
+    }
 }
 
 impl<const LANES: usize> Add for Simd<LANES>
@@ -40,7 +92,11 @@ where
 {
     type Output = Simd<LANES>;
     fn add(self, rhs: Self) -> Self::Output {
-        Simd::Float(self.as_float() + rhs.as_float())
+        if self.is_float(&rhs) {
+            Simd::Float(self.as_float() + rhs.as_float())
+        } else {
+            Simd::Int(self.as_int() + rhs.as_int())
+        }
     }
 }
 impl<const LANES: usize> Sub for Simd<LANES>
@@ -50,7 +106,11 @@ where
     type Output = Simd<LANES>;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        Simd::Float(self.as_float() - rhs.as_float())
+        if self.is_float(&rhs) {
+            Simd::Float(self.as_float() - rhs.as_float())
+        } else {
+            Simd::Int(self.as_int() - rhs.as_int())
+        }
     }
 }
 impl<const LANES: usize> Mul for Simd<LANES>
@@ -59,7 +119,11 @@ where
 {
     type Output = Simd<LANES>;
     fn mul(self, rhs: Self) -> Self::Output {
-        Simd::Float(self.as_float() * rhs.as_float())
+        if self.is_float(&rhs) {
+            Simd::Float(self.as_float() * rhs.as_float())
+        } else {
+            Simd::Int(self.as_int() * rhs.as_int())
+        }
     }
 }
 
@@ -69,7 +133,15 @@ where
 {
     type Output = Simd<LANES>;
     fn div(self, rhs: Self) -> Self::Output {
-        Simd::Float(self.as_float() / rhs.as_float())
+        if self.is_float(&rhs) {
+            Simd::Float(self.as_float() / rhs.as_float())
+        } else {
+            // like `div_int` a division by zero is 0
+            let (lhs, rhs) = (self.as_int(), rhs.as_int());
+            let zero = rhs.simd_eq(simd::Simd::splat(0));
+            let quotient = lhs / zero.select(simd::Simd::splat(1), rhs);
+            Simd::Int(zero.select(simd::Simd::splat(0), quotient))
+        }
     }
 }
 impl<const LANES: usize> Rem for Simd<LANES>
@@ -78,146 +150,141 @@ where
 {
     type Output = Simd<LANES>;
     fn rem(self, rhs: Self) -> Self::Output {
-        Simd::Float(self.as_float() % rhs.as_float())
-    }
-}
-
-impl<const LANES: usize> Simd<LANES>
-where
-    LaneCount<LANES>: SupportedLaneCount,
-{
-    pub(crate) fn pow(self, rhs: Self) -> Self {
-        let lhs = self.as_float().to_array();
-        let rhs = self.as_float().to_array();
-        Simd::Float(lhs.zip(rhs).map(|(lhs, rhs)| lhs.powf(rhs)).into())
+        if self.is_float(&rhs) {
+            Simd::Float(self.as_float() % rhs.as_float())
+        } else {
+            // like `rem_int` the remainder of a division by zero is the dividend
+            let (lhs, rhs) = (self.as_int(), rhs.as_int());
+            let zero = rhs.simd_eq(simd::Simd::splat(0));
+            Simd::Int(zero.select(lhs, lhs % zero.select(simd::Simd::splat(1), rhs)))
+        }
     }
 }
-/*
-impl<const LANES: usize> Add for Simd<LANES>
+impl<const LANES: usize> Neg for Simd<LANES>
 where
     LaneCount<LANES>: SupportedLaneCount,
 {
     type Output = Simd<LANES>;
-    fn add(self, rhs: Self) -> Self::Output {
+    fn neg(self) -> Self::Output {
         match self {
-            Simd::Int(l) => match rhs {
-                Simd::Int(r) => Simd::Int(l + r),
-                Simd::Float(r) => Simd::Float(<simd::Simd<f64, { LANES }>>::from(l) + r),
-                Simd::Bool(r) => Simd::Int(l + r as i64),
-            },
-            Simd::Float(l) => match rhs {
-                Simd::Int(r) => Simd::Float(l + r as f64),
-                Simd::Float(r) => Simd::Float(l + r),
-                Simd::Bool(r) => Simd::Float(l + r as i64 as f64),
-            },
-            Simd::Bool(l) => match rhs {
-                Simd::Int(r) => Simd::Int(l as i64 + r),
-                Simd::Float(r) => Simd::Float(l as i64 as f64 + r),
-                Simd::Bool(r) => Simd::Int(l as i64 + r as i64),
-            },
+            Simd::Float(v) => Simd::Float(-v),
+            _ => Simd::Int(-self.as_int()),
         }
     }
 }
-impl<const LANES: usize> Sub for Simd<LANES>
+
+impl<const LANES: usize> Simd<LANES>
 where
     LaneCount<LANES>: SupportedLaneCount,
 {
-    type Output = Simd<LANES>;
-
-    fn sub(self, rhs: Self) -> Self::Output {
-        match self {
-            Simd::Int(l) => match rhs {
-                Simd::Int(r) => Simd::Int(l - r),
-                Simd::Float(r) => Simd::Float(l as f64 - r),
-                Simd::Bool(r) => Simd::Int(l - r as i64),
-            },
-            Simd::Float(l) => match rhs {
-                Simd::Int(r) => Simd::Float(l - r as f64),
-                Simd::Float(r) => Simd::Float(l - r),
-                Simd::Bool(r) => Simd::Float(l - r as i64 as f64),
-            },
-            Simd::Bool(l) => match rhs {
-                Simd::Int(r) => Simd::Int(l as i64 - r),
-                Simd::Float(r) => Simd::Float(l as i64 as f64 - r),
-                Simd::Bool(r) => Simd::Int(l as i64 - r as i64),
-            },
+    /// `self * a + b` rounded once if any operand is a float
+    pub(crate) fn mul_add(self, a: Self, b: Self) -> Self {
+        if self.is_float(&a) || b.is_float(&b) {
+            Simd::Float(self.as_float().mul_add(a.as_float(), b.as_float()))
+        } else {
+            self * a + b
         }
     }
-}
-impl<const LANES: usize> Mul for Simd<LANES>
-where
-    LaneCount<LANES>: SupportedLaneCount,
-{
-    type Output = Simd<LANES>;
-    fn mul(self, rhs: Self) -> Self::Output {
-        match self {
-            Simd::Int(l) => match rhs {
-                Simd::Int(r) => Simd::Int(l * r),
-                Simd::Float(r) => Simd::Float(l as f64 * r),
-                Simd::Bool(r) => Simd::Int(l * r as i64),
-            },
-            Simd::Float(l) => match rhs {
-                Simd::Int(r) => Simd::Float(l * r as f64),
-                Simd::Float(r) => Simd::Float(l * r),
-                Simd::Bool(r) => Simd::Float(l * r as i64 as f64),
-            },
-            Simd::Bool(l) => match rhs {
-                Simd::Int(r) => Simd::Int(l as i64 * r),
-                Simd::Float(r) => Simd::Float(l as i64 as f64 * r),
-                Simd::Bool(r) => Simd::Int(l as i64 * r as i64),
-            },
+    /// like `Single::pow` an int raised to positive int exponents stays an int,
+    /// the result is only an int if the exponent is positive in every lane
+    pub(crate) fn pow(self, rhs: Self) -> Self {
+        match (self, rhs) {
+            (Simd::Bool(_), _) => self,
+            (Simd::Int(lhs), Simd::Bool(_)) => {
+                let rhs = rhs.as_int();
+                Simd::Int(simd::Simd::from_array(array::from_fn(|i| {
+                    lhs[i].wrapping_pow(rhs[i] as u32)
+                })))
+            }
+            (Simd::Int(lhs), Simd::Int(rhs)) if rhs.simd_gt(simd::Simd::splat(0)).all() => {
+                Simd::Int(simd::Simd::from_array(array::from_fn(|i| {
+                    lhs[i].wrapping_pow(rhs[i] as u32)
+                })))
+            }
+            _ => {
+                Simd::Float(math::pow(self.as_float(), rhs.as_float()))
+            }
         }
     }
-}
-
-impl<const LANES: usize> Div for Simd<LANES>
-where
-    LaneCount<LANES>: SupportedLaneCount,
-{
-    type Output = Simd<LANES>;
-    fn div(self, rhs: Self) -> Self::Output {
-        match self {
-            Simd::Int(l) => match rhs {
-                Simd::Int(r) => Simd::Int(l / r),
-                Simd::Float(r) => Simd::Float(l.try_into().unwrap() / r),
-                Simd::Bool(r) => Simd::Int(l / r.into()),
-            },
-            Simd::Float(l) => match rhs {
-                Simd::Int(r) => Simd::Float(l / r.into()),
-                Simd::Float(r) => Simd::Float(l / r),
-                Simd::Bool(r) => Simd::Float(l / r.into()),
-            },
-            Simd::Bool(l) => match rhs {
-                Simd::Int(r) => Simd::Int(l.into() / r),
-                Simd::Float(r) => Simd::Float(l.into() / r),
-                Simd::Bool(r) => Simd::Int(l.into() / r.into()),
-            },
+    pub(crate) fn equal(self, rhs: Self) -> Self {
+        Simd::Bool(if self.is_float(&rhs) {
+            self.as_float().simd_eq(rhs.as_float())
+        } else {
+            self.as_int().simd_eq(rhs.as_int())
+        })
+    }
+    pub(crate) fn not_equal(self, rhs: Self) -> Self {
+        Simd::Bool(if self.is_float(&rhs) {
+            self.as_float().simd_ne(rhs.as_float())
+        } else {
+            self.as_int().simd_ne(rhs.as_int())
+        })
+    }
+    pub(crate) fn greater(self, rhs: Self) -> Self {
+        Simd::Bool(if self.is_float(&rhs) {
+            self.as_float().simd_gt(rhs.as_float())
+        } else {
+            self.as_int().simd_gt(rhs.as_int())
+        })
+    }
+    pub(crate) fn greater_equal(self, rhs: Self) -> Self {
+        Simd::Bool(if self.is_float(&rhs) {
+            self.as_float().simd_ge(rhs.as_float())
+        } else {
+            self.as_int().simd_ge(rhs.as_int())
+        })
+    }
+    pub(crate) fn smaller(self, rhs: Self) -> Self {
+        Simd::Bool(if self.is_float(&rhs) {
+            self.as_float().simd_lt(rhs.as_float())
+        } else {
+            self.as_int().simd_lt(rhs.as_int())
+        })
+    }
+    pub(crate) fn smaller_equal(self, rhs: Self) -> Self {
+        Simd::Bool(if self.is_float(&rhs) {
+            self.as_float().simd_le(rhs.as_float())
+        } else {
+            self.as_int().simd_le(rhs.as_int())
+        })
+    }
+    pub(crate) fn min(self, rhs: Self) -> Self {
+        if self.is_float(&rhs) {
+            Simd::Float(self.as_float().simd_min(rhs.as_float()))
+        } else {
+            Simd::Int(self.as_int().simd_min(rhs.as_int()))
         }
     }
-}
-impl<const LANES: usize> Rem for Simd<LANES>
-where
-    LaneCount<LANES>: SupportedLaneCount,
-{
-    type Output = Simd<LANES>;
-    fn rem(self, rhs: Self) -> Self::Output {
-        match self {
-            Simd::Int(l) => match rhs {
-                Simd::Int(r) => Simd::Int(l % r),
-                Simd::Float(r) => Simd::Float(l as f64 % r),
-                Simd::Bool(r) => Simd::Int(l % r as i64),
-            },
-            Simd::Float(l) => match rhs {
-                Simd::Int(r) => Simd::Float(l % r as f64),
-                Simd::Float(r) => Simd::Float(l % r),
-                Simd::Bool(r) => Simd::Float(l % r as i64 as f64),
-            },
-            Simd::Bool(l) => match rhs {
-                Simd::Int(r) => Simd::Int(l as i64 % r),
-                Simd::Float(r) => Simd::Float(l as i64 as f64 % r),
-                Simd::Bool(r) => Simd::Int(l as i64 % r as i64),
-            },
+    pub(crate) fn max(self, rhs: Self) -> Self {
+        if self.is_float(&rhs) {
+            Simd::Float(self.as_float().simd_max(rhs.as_float()))
+        } else {
+            Simd::Int(self.as_int().simd_max(rhs.as_int()))
         }
     }
+    pub(crate) fn not(self) -> Self {
+        Simd::Bool(!self.as_mask())
+    }
+    /// takes the lanes of `then` where `mask` is set and the lanes of `otherwise` elsewhere,
+    /// the result is promoted like the result of an arithmetic operation
+    pub(crate) fn select(mask: Mask<i64, LANES>, then: Self, otherwise: Self) -> Self {
+        match (then, otherwise) {
+            (Simd::Bool(then), Simd::Bool(otherwise)) => {
+                Simd::Bool((mask & then) | (!mask & otherwise))
+            }
+            _ if then.is_float(&otherwise) => {
+                Simd::Float(mask.select(then.as_float(), otherwise.as_float()))
+            }
+            _ => Simd::Int(mask.select(then.as_int(), otherwise.as_int())),
+        }
+    }
+    pub(crate) fn and(self, rhs: Self) -> Self {
+        Simd::Bool(self.as_mask() & rhs.as_mask())
+    }
+    pub(crate) fn or(self, rhs: Self) -> Self {
+        Simd::Bool(self.as_mask() | rhs.as_mask())
+    }
+    pub(crate) fn xor(self, rhs: Self) -> Self {
+        Simd::Bool(self.as_mask() ^ rhs.as_mask())
+    }
 }
-*/
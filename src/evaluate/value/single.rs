@@ -1,5 +1,6 @@
 use std::{
-    ops::{Add, Div, Mul, Rem, Sub},
+    cmp::Ordering,
+    ops::{Add, Div, Mul, Neg, Rem, Sub},
     simd::{self, f64x8, i64x8, LaneCount, SupportedLaneCount},
 };
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
@@ -69,9 +70,9 @@ impl Add for Single {
     fn add(self, rhs: Self) -> Self::Output {
         match self {
             Single::Int(l) => match rhs {
-                Single::Int(r) => Single::Int(l + r),
+                Single::Int(r) => Single::Int(l.wrapping_add(r)),
                 Single::Float(r) => Single::Float(l as f64 + r),
-                Single::Bool(r) => Single::Int(l + r as i64),
+                Single::Bool(r) => Single::Int(l.wrapping_add(r as i64)),
             },
             Single::Float(l) => match rhs {
                 Single::Int(r) => Single::Float(l + r as f64),
@@ -79,9 +80,9 @@ impl Add for Single {
                 Single::Bool(r) => Single::Float(l + r as i64 as f64),
             },
             Single::Bool(l) => match rhs {
-                Single::Int(r) => Single::Int(l as i64 + r),
+                Single::Int(r) => Single::Int((l as i64).wrapping_add(r)),
                 Single::Float(r) => Single::Float(l as i64 as f64 + r),
-                Single::Bool(r) => Single::Int(l as i64 + r as i64),
+                Single::Bool(r) => Single::Int((l as i64).wrapping_add(r as i64)),
             },
         }
     }
@@ -92,9 +93,9 @@ impl Sub for Single {
     fn sub(self, rhs: Self) -> Self::Output {
         match self {
             Single::Int(l) => match rhs {
-                Single::Int(r) => Single::Int(l - r),
+                Single::Int(r) => Single::Int(l.wrapping_sub(r)),
                 Single::Float(r) => Single::Float(l as f64 - r),
-                Single::Bool(r) => Single::Int(l - r as i64),
+                Single::Bool(r) => Single::Int(l.wrapping_sub(r as i64)),
             },
             Single::Float(l) => match rhs {
                 Single::Int(r) => Single::Float(l - r as f64),
@@ -102,9 +103,9 @@ impl Sub for Single {
                 Single::Bool(r) => Single::Float(l - r as i64 as f64),
             },
             Single::Bool(l) => match rhs {
-                Single::Int(r) => Single::Int(l as i64 - r),
+                Single::Int(r) => Single::Int((l as i64).wrapping_sub(r)),
                 Single::Float(r) => Single::Float(l as i64 as f64 - r),
-                Single::Bool(r) => Single::Int(l as i64 - r as i64),
+                Single::Bool(r) => Single::Int((l as i64).wrapping_sub(r as i64)),
             },
         }
     }
@@ -114,9 +115,9 @@ impl Mul for Single {
     fn mul(self, rhs: Self) -> Self::Output {
         match self {
             Single::Int(l) => match rhs {
-                Single::Int(r) => Single::Int(l * r),
+                Single::Int(r) => Single::Int(l.wrapping_mul(r)),
                 Single::Float(r) => Single::Float(l as f64 * r),
-                Single::Bool(r) => Single::Int(l * r as i64),
+                Single::Bool(r) => Single::Int(l.wrapping_mul(r as i64)),
             },
             Single::Float(l) => match rhs {
                 Single::Int(r) => Single::Float(l * r as f64),
@@ -124,22 +125,41 @@ impl Mul for Single {
                 Single::Bool(r) => Single::Float(l * r as i64 as f64),
             },
             Single::Bool(l) => match rhs {
-                Single::Int(r) => Single::Int(l as i64 * r),
+                Single::Int(r) => Single::Int((l as i64).wrapping_mul(r)),
                 Single::Float(r) => Single::Float(l as i64 as f64 * r),
-                Single::Bool(r) => Single::Int(l as i64 * r as i64),
+                Single::Bool(r) => Single::Int((l as i64).wrapping_mul(r as i64)),
             },
         }
     }
 }
 
+/// divides ints like `wrapping_div`, but a division by zero is `0`
+/// instead of a panic so every lane of a `Simd` has a result
+pub(crate) fn div_int(lhs: i64, rhs: i64) -> i64 {
+    if rhs == 0 {
+        0
+    } else {
+        lhs.wrapping_div(rhs)
+    }
+}
+/// the remainder of `div_int`, `lhs` for a division by zero so that
+/// `lhs == lhs / rhs * rhs + lhs % rhs` still holds
+pub(crate) fn rem_int(lhs: i64, rhs: i64) -> i64 {
+    if rhs == 0 {
+        lhs
+    } else {
+        lhs.wrapping_rem(rhs)
+    }
+}
+
 impl Div for Single {
     type Output = Single;
     fn div(self, rhs: Self) -> Self::Output {
         match self {
             Single::Int(l) => match rhs {
-                Single::Int(r) => Single::Int(l / r),
+                Single::Int(r) => Single::Int(div_int(l, r)),
                 Single::Float(r) => Single::Float(l as f64 / r),
-                Single::Bool(r) => Single::Int(l / r as i64),
+                Single::Bool(r) => Single::Int(div_int(l, r as i64)),
             },
             Single::Float(l) => match rhs {
                 Single::Int(r) => Single::Float(l / r as f64),
@@ -147,9 +167,9 @@ impl Div for Single {
                 Single::Bool(r) => Single::Float(l / r as i64 as f64),
             },
             Single::Bool(l) => match rhs {
-                Single::Int(r) => Single::Int(l as i64 / r),
+                Single::Int(r) => Single::Int(div_int(l as i64, r)),
                 Single::Float(r) => Single::Float(l as i64 as f64 / r),
-                Single::Bool(r) => Single::Int(l as i64 / r as i64),
+                Single::Bool(r) => Single::Int(div_int(l as i64, r as i64)),
             },
         }
     }
@@ -159,9 +179,9 @@ impl Rem for Single {
     fn rem(self, rhs: Self) -> Self::Output {
         match self {
             Single::Int(l) => match rhs {
-                Single::Int(r) => Single::Int(l % r),
+                Single::Int(r) => Single::Int(rem_int(l, r)),
                 Single::Float(r) => Single::Float(l as f64 % r),
-                Single::Bool(r) => Single::Int(l % r as i64),
+                Single::Bool(r) => Single::Int(rem_int(l, r as i64)),
             },
             Single::Float(l) => match rhs {
                 Single::Int(r) => Single::Float(l % r as f64),
@@ -169,32 +189,53 @@ impl Rem for Single {
                 Single::Bool(r) => Single::Float(l % r as i64 as f64),
             },
             Single::Bool(l) => match rhs {
-                Single::Int(r) => Single::Int(l as i64 % r),
+                Single::Int(r) => Single::Int(rem_int(l as i64, r)),
                 Single::Float(r) => Single::Float(l as i64 as f64 % r),
-                Single::Bool(r) => Single::Int(l as i64 % r as i64),
+                Single::Bool(r) => Single::Int(rem_int(l as i64, r as i64)),
             },
         }
     }
 }
+impl Neg for Single {
+    type Output = Single;
+    fn neg(self) -> Self::Output {
+        match self {
+            Single::Int(v) => Single::Int(v.wrapping_neg()),
+            Single::Float(v) => Single::Float(-v),
+            Single::Bool(v) => Single::Int(-(v as i64)),
+        }
+    }
+}
 
 impl Single {
+    /// `self * a + b` rounded once if any operand is a float
+    pub(crate) fn mul_add(self, a: Self, b: Self) -> Single {
+        match (self, a, b) {
+            (Single::Float(_), _, _) | (_, Single::Float(_), _) | (_, _, Single::Float(_)) => {
+                Single::Float(self.as_float().mul_add(a.as_float(), b.as_float()))
+            }
+            _ => self * a + b,
+        }
+    }
+    /// ints raised to positive int exponents stay ints, ints wrap around on overflow like in `Simd`
     pub(crate) fn pow(self, rhs: Self) -> Single {
         match self {
             Single::Int(l) => match rhs {
                 Single::Int(r) => {
                     if r.is_positive() {
-                        Single::Int(l.pow(r.try_into().expect("exponent out of range")))
+                        Single::Int(l.wrapping_pow(r as u32))
                     } else {
-                        Single::Float((l as f64).powi(r.try_into().expect("exponent out of range")))
+                        Single::Float((l as f64).powf(r as f64))
                     }
                 }
                 Single::Float(r) => Single::Float((l as f64).powf(r)),
-                Single::Bool(r) => Single::Int(l.pow(r as u32)),
+                Single::Bool(r) => Single::Int(l.wrapping_pow(r as u32)),
             },
             Single::Float(l) => match rhs {
-                Single::Int(r) => {
-                    Single::Float(l.powi(r.try_into().expect("exponent ouf of range")))
-                }
+                Single::Int(r) => Single::Float(match i32::try_from(r) {
+                    Ok(r) => l.powi(r),
+                    Err(_) => l.powf(r as f64),
+                }),
                 Single::Float(r) => Single::Float(l.powf(r)),
                 Single::Bool(r) => Single::Float(l.powi(r as i32)),
             },
@@ -205,6 +246,60 @@ impl Single {
             },
         }
     }
+    /// compares two values, ints and bools are compared exactly, anything involving a float as floats
+    fn compare(self, rhs: Self) -> Option<Ordering> {
+        match (self, rhs) {
+            (Single::Float(_), _) | (_, Single::Float(_)) => {
+                self.as_float().partial_cmp(&rhs.as_float())
+            }
+            _ => Some(self.as_int().cmp(&rhs.as_int())),
+        }
+    }
+    pub(crate) fn equal(self, rhs: Self) -> Single {
+        Single::Bool(self.compare(rhs) == Some(Ordering::Equal))
+    }
+    pub(crate) fn not_equal(self, rhs: Self) -> Single {
+        Single::Bool(self.compare(rhs) != Some(Ordering::Equal))
+    }
+    pub(crate) fn greater(self, rhs: Self) -> Single {
+        Single::Bool(self.compare(rhs) == Some(Ordering::Greater))
+    }
+    pub(crate) fn greater_equal(self, rhs: Self) -> Single {
+        Single::Bool(matches!(
+            self.compare(rhs),
+            Some(Ordering::Greater | Ordering::Equal)
+        ))
+    }
+    pub(crate) fn smaller(self, rhs: Self) -> Single {
+        Single::Bool(self.compare(rhs) == Some(Ordering::Less))
+    }
+    pub(crate) fn smaller_equal(self, rhs: Self) -> Single {
+        Single::Bool(matches!(
+            self.compare(rhs),
+            Some(Ordering::Less | Ordering::Equal)
+        ))
+    }
+    /// the smaller of both values, a float if either of them is one
+    pub(crate) fn min(self, rhs: Self) -> Single {
+        match (self, rhs) {
+            (Single::Float(_), _) | (_, Single::Float(_)) => {
+                Single::Float(self.as_float().min(rhs.as_float()))
+            }
+            _ => Single::Int(self.as_int().min(rhs.as_int())),
+        }
+    }
+    /// the larger of both values, a float if either of them is one
+    pub(crate) fn max(self, rhs: Self) -> Single {
+        match (self, rhs) {
+            (Single::Float(_), _) | (_, Single::Float(_)) => {
+                Single::Float(self.as_float().max(rhs.as_float()))
+            }
+            _ => Single::Int(self.as_int().max(rhs.as_int())),
+        }
+    }
+    pub(crate) fn not(self) -> Single {
+        Single::Bool(!self.as_bool())
+    }
     pub(crate) fn and(self, rhs: Self) -> Single {
         let lhs = match self {
             Single::Int(l) => l != 0,
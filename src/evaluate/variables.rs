@@ -8,13 +8,17 @@ use crate::{error::Error, small_string::SmallString};
 
 use super::value::{single::Single, Value};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct Variables<const LANES: usize>
 where
     LaneCount<LANES>: SupportedLaneCount,
 {
     identifiers: HashMap<SmallString<16>, usize>,
     values: Vec<Value<LANES>>,
+    /// the number of variables that are assigned by a script, they come before the temporaries
+    assigned: usize,
+    /// the number of unnamed variables at the end holding the values of shared subexpressions
+    temporaries: usize,
 }
 impl<'a, const LANES: usize> Variables<LANES>
 where
@@ -23,11 +27,15 @@ where
     pub(crate) fn clear(&mut self) {
         self.identifiers.clear();
         self.values.clear();
+        self.assigned = 0;
+        self.temporaries = 0;
     }
     pub(crate) fn with_capacity(capacity: usize) -> Self {
         Self {
             identifiers: HashMap::with_capacity(capacity),
             values: Vec::with_capacity(capacity),
+            assigned: 0,
+            temporaries: 0,
         }
     }
     pub(crate) fn push(&mut self, identifier: &'a str) {
@@ -40,13 +48,54 @@ where
         identifier: &'a str,
         value: Value<LANES>,
     ) -> Result<(), Error> {
-        let index = *self
-            .identifiers
-            .get(identifier)
-            .ok_or(Error::InvalidVariable)?;
+        let index = self.index_of(identifier)?;
         self.values[index] = value;
         Ok(())
     }
+    pub(crate) fn index_of(&self, identifier: &str) -> Result<usize, Error> {
+        self.identifiers
+            .get(identifier)
+            .copied()
+            .ok_or(Error::InvalidVariable)
+    }
+    pub(crate) fn values(&self) -> &[Value<LANES>] {
+        &self.values
+    }
+    pub(crate) fn values_mut(&mut self) -> &mut [Value<LANES>] {
+        &mut self.values
+    }
+    /// the number of variables that aren't assigned by a script or the program
+    pub(crate) fn inputs(&self) -> usize {
+        self.values.len() - self.assigned - self.temporaries
+    }
+    /// adds an unnamed variable at the end and returns its index
+    pub(crate) fn push_temporary(&mut self) -> usize {
+        self.temporaries += 1;
+        self.values.push(Value::Single(Single::Int(0)));
+        self.values.len() - 1
+    }
+    pub(crate) fn clear_temporaries(&mut self) {
+        self.values.truncate(self.values.len() - self.temporaries);
+        self.temporaries = 0;
+    }
+    /// moves the variables marked in `assigned` behind the others, keeping their order otherwise,
+    /// and returns the new index of every variable. the temporaries are removed
+    pub(crate) fn move_to_end(&mut self, assigned: &[bool]) -> Vec<usize> {
+        self.clear_temporaries();
+        let (mut order, moved): (Vec<usize>, Vec<usize>) =
+            (0..self.values.len()).partition(|&index| !assigned[index]);
+        self.assigned = moved.len();
+        order.extend(moved);
+        let mut mapping = vec![0; order.len()];
+        for (new, &old) in order.iter().enumerate() {
+            mapping[old] = new;
+        }
+        self.values = order.iter().map(|&old| self.values[old]).collect();
+        for index in self.identifiers.values_mut() {
+            *index = mapping[*index];
+        }
+        mapping
+    }
     pub(crate) fn set_by_index(
         &mut self,
         index: usize,
@@ -99,6 +148,8 @@ where
         Self {
             identifiers: Default::default(),
             values: Default::default(),
+            assigned: Default::default(),
+            temporaries: Default::default(),
         }
     }
 }
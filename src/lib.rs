@@ -5,8 +5,6 @@
 
 #![feature(more_qualified_paths)]
 #![feature(portable_simd)]
-#![feature(generic_const_exprs)]
-#![feature(array_zip)]
 
 
 #![allow(dead_code)]
@@ -23,8 +21,11 @@
 //! invoked using function like syntax.
 
 pub use error::*;
-pub use evaluate::expression::Expression;
+pub use evaluate::batch::{Column, ColumnMut};
+pub use evaluate::expression::{Expression, OptimizationLevel, MAX_DEPTH};
+pub use evaluate::function::registry::{FunctionRegistry, RegistryFunction};
 pub use evaluate::function::std::Std;
+pub use evaluate::program::{Frame, Program};
 
 #[macro_use]
 
@@ -2,18 +2,17 @@ use std::{borrow::Borrow, hash::Hash, str::from_utf8};
 
 use crate::stack::Stack;
 
-#[derive(Debug, Eq)]
+/// a string stored inline if it is at most `SIZE` bytes long, otherwise on the heap
+///
+/// the limit is in bytes, not characters, so multibyte names switch to the heap earlier
+#[derive(Debug, Clone, Eq)]
 pub(crate) enum SmallString<const SIZE: usize> {
     Small(Stack<u8, SIZE>),
     String(String),
 }
 impl<const SIZE: usize> PartialEq for SmallString<SIZE> {
     fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Self::Small(l0), Self::Small(r0)) => l0 == r0,
-            (Self::String(l0), Self::String(r0)) => l0 == r0,
-            _ => false,
-        }
+        self.as_str() == other.as_str()
     }
 }
 impl<const SIZE: usize> Hash for SmallString<SIZE> {
@@ -40,19 +39,11 @@ impl<const SIZE: usize> SmallString<SIZE> {
 }
 impl<const SIZE: usize> Borrow<str> for SmallString<SIZE> {
     fn borrow(&self) -> &str {
-        match self {
-            Self::String(string) => string,
-            Self::Small(small) => from_utf8(small.slice()).unwrap(),
-        }
+        self.as_str()
     }
 }
 impl<const SIZE: usize> From<&str> for SmallString<SIZE> {
     fn from(string: &str) -> Self {
-        let bytes = string.as_bytes();
-        if bytes.len() > SIZE {
-            Self::String(string.to_string())
-        } else {
-            Self::Small(bytes.into())
-        }
+        Self::new(string)
     }
 }
@@ -20,8 +20,7 @@ where
 
 impl<const SIZE: usize> Hash for Stack<u8, SIZE> {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        dbg!(self.slice()).hash(state);
-        dbg!(&state.finish());
+        self.slice().hash(state);
     }
 }
 impl<const SIZE: usize> Eq for Stack<u8, SIZE> {
@@ -106,6 +105,10 @@ where
     pub fn len(&self) -> usize {
         self.index
     }
+    /// returns true if nothing is on the stack
+    pub fn is_empty(&self) -> bool {
+        self.index == 0
+    }
 }
 
 impl<T, const N: usize> Debug for Stack<T, N>
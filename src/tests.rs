@@ -4,14 +4,25 @@ extern crate test;
 use std::{
     collections::BTreeMap,
     mem::{align_of, size_of},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     time::Instant,
 };
 
 use crate::{
     biggest,
+    error::{Error, Span},
     evaluate::{
-        expression::Expression,
-        function::{std::Std, Function},
+        batch::{Column, ColumnMut},
+        expression::{Expression, OptimizationLevel, MAX_DEPTH},
+        function::{
+            registry::{FunctionRegistry, RegistryFunction},
+            std::Std,
+            Function,
+        },
+        instruction::Instruction,
         node::Node,
         parse_element::ParseElement,
         token::Token,
@@ -30,8 +41,8 @@ fn test_parse_fast() {
     println!("tokens: {:#?}", expression);
     expression.to_nodes::<4>().unwrap();
     println!("nodes: {:#?}", expression);
-    expression.set_indices().unwrap();
-    println!("indices: {:#?}", expression);
+    expression.parse().unwrap();
+    println!("tree: {:#?}", expression);
     
     expression.optimize().unwrap();
 
@@ -79,14 +90,13 @@ fn bench_compile_to_nodes(b: &mut test::Bencher) {
 }
 
 #[bench]
-fn bench_compile_set_indices(b: &mut test::Bencher) {
+fn bench_compile_parse(b: &mut test::Bencher) {
     let expression = "a+43*3-a+b^3".to_string();
     let mut test = Expression::<Std<8>, 8>::new(expression.to_owned());
-    test.compile().unwrap();
     b.iter(|| {
         test.to_tokens().unwrap();
         test.to_nodes::<4>().unwrap();
-        test.set_indices().unwrap();
+        test.parse().unwrap();
         test.set_expression(expression.to_owned());
     })
 }
@@ -282,3 +292,1261 @@ fn test_eval_normal() {
     let end = start.elapsed();
     println!("{}ms", end.as_millis())
 }
+
+#[test]
+fn test_error_span() {
+    let mut expression = Expression::<Std<1>, 1>::new("2 + $ 3".to_string());
+    let error = expression.compile().unwrap_err();
+    assert_eq!(error.span(), Some(Span::new(4, 5)));
+    assert_eq!(
+        error.render(expression.string()),
+        "error: unknown character `$`\n | 2 + $ 3\n |     ^"
+    );
+
+    let mut expression = Expression::<Std<1>, 1>::new("1 + foo(2)".to_string());
+    let error = expression.compile().unwrap_err();
+    assert!(matches!(error, Error::NoIdentifierMatch(_)));
+    assert_eq!(
+        error.render(expression.string()),
+        "error: no function with this name\n | 1 + foo(2)\n |     ^^^"
+    );
+}
+
+fn eval_single(expression: &str) -> Value<1> {
+    let mut expression = Expression::<Std<1>, 1>::new(expression.to_string());
+    expression.compile().unwrap();
+    expression.eval().unwrap()
+}
+
+#[test]
+fn test_precedence() {
+    assert_eq!(eval_single("2+3*4"), Value::Single(Single::Int(14)));
+    assert_eq!(eval_single("10-4-3"), Value::Single(Single::Int(3)));
+    assert_eq!(eval_single("2^3^2"), Value::Single(Single::Int(512)));
+    assert_eq!(eval_single("-2^2"), Value::Single(Single::Int(-4)));
+    assert_eq!(eval_single("2*-3"), Value::Single(Single::Int(-6)));
+    assert_eq!(eval_single("3 - -(1+1)"), Value::Single(Single::Int(5)));
+    assert_eq!(eval_single("((((((((((((1+2))))))))))))*3"), Value::Single(Single::Int(9)));
+    assert_eq!(eval_single("abs(2-5)*std:abs(-2)"), Value::Single(Single::Int(6)));
+    assert_eq!(eval_single("log(8, 2)"), Value::Single(Single::Float(3.0)));
+}
+
+#[test]
+fn test_parse_errors() {
+    for (expression, span) in [("(1+2", Span::new(0, 1)), ("1+*2", Span::new(2, 3)), ("1+", Span::new(2, 2))] {
+        let mut expression = Expression::<Std<1>, 1>::new(expression.to_string());
+        assert_eq!(expression.compile().unwrap_err().span(), Some(span));
+    }
+}
+
+#[test]
+fn test_nesting_depth() {
+    let nested = |n: usize| {
+        [
+            format!("{}a{}", "(".repeat(n), ")".repeat(n)),
+            format!("{}a", "-".repeat(n)),
+            format!("a{}", "+a".repeat(n)),
+            format!("a{}", "^a".repeat(n)),
+            format!("{}a{}", "sin(".repeat(n), ")".repeat(n)),
+            format!("{}a", "c ? a : ".repeat(n)),
+        ]
+    };
+    // the deepest expressions that are accepted go through every pass
+    for source in nested(MAX_DEPTH - 2) {
+        let mut expression = Expression::<Std<4>, 4>::new(source.clone());
+        expression.compile().unwrap();
+        expression.optimize_with(OptimizationLevel::FastMath).unwrap();
+        expression.set_variable("a", Value::Single(Single::Float(0.5))).unwrap();
+        expression.eval().unwrap();
+    }
+    for source in nested(MAX_DEPTH) {
+        let mut expression = Expression::<Std<4>, 4>::new(source.clone());
+        let error = expression.compile().unwrap_err();
+        assert!(matches!(error, Error::TooDeep(_)), "{error:?}");
+    }
+    let mut expression =
+        Expression::<Std<1>, 1>::new(format!("{}a{}", "(".repeat(100_000), ")".repeat(100_000)));
+    let error = expression.compile().unwrap_err();
+    assert!(matches!(error, Error::TooDeep(_)), "{error:?}");
+    assert_eq!(error.span(), Some(Span::new(MAX_DEPTH, MAX_DEPTH + 1)));
+    // inlined bodies count as well
+    let source = format!("{}a{}", "f(".repeat(300), ")".repeat(300));
+    let mut expression = Expression::<Std<1>, 1>::new(source);
+    expression.define("f(x) = x + 1 + 1").unwrap();
+    assert!(matches!(expression.compile().unwrap_err(), Error::TooDeep(_)));
+}
+
+#[test]
+fn test_comparison() {
+    let t = Value::Single(Single::Bool(true));
+    let f = Value::Single(Single::Bool(false));
+    assert_eq!(eval_single("1 == 1.0"), t);
+    assert_eq!(eval_single("1 != 1"), f);
+    assert_eq!(eval_single("2 >= 1 && 1 <= 2"), t);
+    assert_eq!(eval_single("2 => 1 && 1 =< 2 && 1 =! 2"), t);
+    // only single characters join into two character operators
+    assert_eq!(eval_single("1==!0"), t);
+    assert_eq!(eval_single("true&&!false"), t);
+    assert!(Expression::<Std<1>, 1>::new("1 === 1".to_string()).compile().is_err());
+    assert_eq!(eval_single("1+1 > 2 || 3 < 2"), f);
+    assert_eq!(eval_single("!(1 > 2) # 0"), t);
+    assert_eq!(eval_single("!0 && 5"), t);
+
+    let mut expression = Expression::<Std<4>, 4>::new("a > 1.5 && a != 3.0".to_string());
+    expression.compile().unwrap();
+    expression
+        .set_variable("a", Value::Simd(Simd::Float([1.0, 2.0, 3.0, 4.0].into())))
+        .unwrap();
+    expression.to_simd();
+    let mask = expression.eval().unwrap();
+    assert_eq!(
+        mask,
+        Value::Simd(Simd::Bool([false, true, false, true].into()))
+    );
+}
+
+#[test]
+fn test_broadcast() {
+    let mut expression = Expression::<Std<4>, 4>::new("a * 2 + sqrt(4) - (a > 2)".to_string());
+    expression.compile().unwrap();
+    expression
+        .set_variable("a", Value::Simd(Simd::Float([1.0, 2.0, 3.0, 4.0].into())))
+        .unwrap();
+    assert_eq!(
+        expression.eval().unwrap(),
+        Value::Simd(Simd::Float([4.0, 6.0, 7.0, 9.0].into()))
+    );
+}
+
+#[test]
+fn test_simd_int_bool() {
+    let mut expression = Expression::<Std<4>, 4>::new("a * 3 / 2 + (a > 2) + b".to_string());
+    expression.compile().unwrap();
+    expression
+        .set_variable("a", Value::Simd(Simd::Int([1, 2, 3, 4].into())))
+        .unwrap();
+    expression
+        .set_variable("b", Value::Single(Single::Int((1 << 53) + 1)))
+        .unwrap();
+    let big = (1 << 53) + 1;
+    assert_eq!(
+        expression.eval().unwrap(),
+        Value::Simd(Simd::Int([big + 1, big + 3, big + 5, big + 7].into()))
+    );
+
+    let mut expression = Expression::<Std<4>, 4>::new("a ^ 2 + a / 2.0".to_string());
+    expression.compile().unwrap();
+    expression
+        .set_variable("a", Value::Simd(Simd::Int([1, 2, 3, 4].into())))
+        .unwrap();
+    assert_eq!(
+        expression.eval().unwrap(),
+        Value::Simd(Simd::Float([1.5, 5.0, 10.5, 18.0].into()))
+    );
+
+    // ints wrap around on overflow, the same for single values and simd lanes
+    let mut expression = Expression::<Std<4>, 4>::new("a ^ 63 * 2 + a ^ b + (c + 1)".to_string());
+    expression.compile().unwrap();
+    expression.set_variable("b", Value::Single(Single::Int(1 << 32))).unwrap();
+    expression.set_variable("c", Value::Single(Single::Int(i64::MAX))).unwrap();
+    let wrapped = [1i64, 2, 3, 4].map(|a| {
+        a.wrapping_pow(63).wrapping_mul(2).wrapping_add(a.wrapping_pow(0)).wrapping_add(i64::MIN)
+    });
+    for (lane, result) in wrapped.iter().enumerate() {
+        let a = Value::Single(Single::Int(lane as i64 + 1));
+        expression.set_variable("a", a).unwrap();
+        assert_eq!(expression.eval().unwrap(), Value::Single(Single::Int(*result)));
+    }
+    expression.set_variable("a", Value::Simd(Simd::Int([1, 2, 3, 4].into()))).unwrap();
+    assert_eq!(expression.eval().unwrap(), Value::Simd(Simd::Int(wrapped.into())));
+    assert_eq!(eval_single("2 ^ -1"), Value::Single(Single::Float(0.5)));
+
+    // an int divided by zero is 0 and the remainder is the dividend, in every lane on its own
+    let mut expression = Expression::<Std<4>, 4>::new("(a / b, a % b)".to_string());
+    expression.compile().unwrap();
+    expression.set_variable("a", Value::Simd(Simd::Int([7, 7, i64::MIN, -7].into()))).unwrap();
+    expression.set_variable("b", Value::Simd(Simd::Int([0, 2, -1, 0].into()))).unwrap();
+    let mut results = [Value::Single(Single::Int(0)); 2];
+    expression.eval_tuple(&mut results).unwrap();
+    assert_eq!(
+        results,
+        [
+            Value::Simd(Simd::Int([0, 3, i64::MIN, 0].into())),
+            Value::Simd(Simd::Int([7, 1, 0, -7].into())),
+        ]
+    );
+    for (a, b, quotient, remainder) in [(7, 0, 0, 7), (7, 2, 3, 1), (i64::MIN, -1, i64::MIN, 0)] {
+        expression.set_variable("a", Value::Single(Single::Int(a))).unwrap();
+        expression.set_variable("b", Value::Single(Single::Int(b))).unwrap();
+        expression.eval_tuple(&mut results).unwrap();
+        assert_eq!(
+            results,
+            [Value::Single(Single::Int(quotient)), Value::Single(Single::Int(remainder))]
+        );
+    }
+    assert_eq!(eval_single("1 / 0.0"), Value::Single(Single::Float(f64::INFINITY)));
+}
+
+/// distance between two floats in units in the last place
+fn ulps(a: f64, b: f64) -> u64 {
+    if a == b || (a.is_nan() && b.is_nan()) {
+        return 0;
+    }
+    let key = |v: f64| {
+        let bits = v.to_bits() as i64;
+        if bits < 0 {
+            i64::MIN - bits
+        } else {
+            bits
+        }
+    };
+    key(a).abs_diff(key(b))
+}
+
+#[test]
+fn test_simd_math_accuracy() {
+    use crate::evaluate::value::math;
+    use std::simd::Simd as Vector;
+
+    let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+    let mut random = |min: f64, max: f64| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        min + (max - min) * (seed >> 11) as f64 / (1u64 << 53) as f64
+    };
+    let check = |name: &str, max_ulps: u64, x: f64, simd: f64, std: f64| {
+        assert!(
+            ulps(simd, std) <= max_ulps,
+            "{}({}) = {} but std returned {}",
+            name,
+            x,
+            simd,
+            std
+        );
+    };
+    for _ in 0..20000 {
+        let x = random(-700.0, 700.0);
+        let v = Vector::<f64, 4>::splat(x);
+        check("exp", 1, x, math::exp(v)[0], x.exp());
+        let x = random(-100000.0, 100000.0);
+        let v = Vector::<f64, 4>::splat(x);
+        check("sin", 1, x, math::sin(v)[0], x.sin());
+        check("cos", 1, x, math::cos(v)[0], x.cos());
+        check("tan", 4, x, math::tan(v)[0], x.tan());
+        let x = random(-30.0, 30.0).exp();
+        let v = Vector::<f64, 4>::splat(x);
+        check("ln", 1, x, math::ln(v)[0], x.ln());
+        let y = random(-50.0, 50.0);
+        let w = Vector::<f64, 4>::splat(y);
+        check("atan2", 2, x, math::atan2(w, v)[0], y.atan2(x));
+        check("atan2", 2, x, math::atan2(w, -v)[0], y.atan2(-x));
+        let e = y / 10.0;
+        let tolerance = 2 + (e * x.ln()).abs() as u64;
+        check("pow", tolerance, x, math::pow(v, Vector::splat(e))[0], x.powf(e));
+        let e = e.round();
+        check("pow", 2 + e.abs() as u64, x, math::pow(v, Vector::splat(e))[0], x.powf(e));
+    }
+    let specials = [0.0, -0.0, 1.0, -1.0, 0.5, -2.0, f64::INFINITY, f64::NEG_INFINITY, f64::NAN, 5e-324];
+    for x in specials {
+        let v = Vector::<f64, 4>::splat(x);
+        check("exp", 1, x, math::exp(v)[0], x.exp());
+        check("ln", 1, x, math::ln(v)[0], x.ln());
+        for y in specials {
+            let w = Vector::<f64, 4>::splat(y);
+            let error = (y * x.abs().ln()).abs();
+            let tolerance = if error.is_finite() { 1 + error as u64 } else { 1 };
+            check("pow", tolerance, x, math::pow(v, w)[0], x.powf(y));
+            check("atan2", 1, y, math::atan2(w, v)[0], y.atan2(x));
+        }
+    }
+}
+
+#[test]
+fn test_simd_functions() {
+    let mut expression = Expression::<Std<4>, 4>::new(
+        "sqrt(a) + abs(-a) + pow(a, 2) + ln(1) + exp(0)".to_string(),
+    );
+    expression.compile().unwrap();
+    expression
+        .set_variable("a", Value::Simd(Simd::Float([1.0, 4.0, 9.0, 16.0].into())))
+        .unwrap();
+    assert_eq!(
+        expression.eval().unwrap(),
+        Value::Simd(Simd::Float([4.0, 23.0, 94.0, 277.0].into()))
+    );
+}
+
+#[test]
+fn test_eval_batch() {
+    let a: Vec<f64> = (0..11).map(|i| i as f64 * 0.5).collect();
+    let b: Vec<i64> = (0..11).collect();
+    let mut expression = Expression::<Std<4>, 4>::new("a * 2 + b".to_string());
+    expression.compile().unwrap();
+    let mut output = vec![0.0; 11];
+    expression
+        .eval_batch(&[Column::Float(&a), Column::Int(&b)], ColumnMut::Float(&mut output))
+        .unwrap();
+    assert_eq!(output, (0..11).map(|i| i as f64 * 2.0).collect::<Vec<_>>());
+
+    let mut expression = Expression::<Std<4>, 4>::new("a > b / 2".to_string());
+    expression.compile().unwrap();
+    let mut output = vec![true; 11];
+    expression
+        .eval_batch(&[Column::Float(&a), Column::Int(&b)], ColumnMut::Bool(&mut output))
+        .unwrap();
+    assert_eq!(output, (0..11).map(|i| i % 2 == 1).collect::<Vec<_>>());
+
+    // the rows after the last full vector are evaluated like the others
+    let x = [0.3, 1.7, 2.9, -4.1, 0.3, 1.7, 2.9, -4.1, 0.3, 1.7];
+    let n = [2, -1, 3, 0, 2, -1, 3, 0, 2, -1];
+    let mut expression = Expression::<Std<4>, 4>::new("sin(x) * exp(x) + 2 ^ n".to_string());
+    expression.compile().unwrap();
+    let mut output = vec![0.0; 10];
+    expression
+        .eval_batch(&[Column::Float(&x), Column::Int(&n)], ColumnMut::Float(&mut output))
+        .unwrap();
+    for row in 4..10 {
+        assert_eq!(output[row].to_bits(), output[row % 4].to_bits(), "row {row}");
+    }
+
+    let mut output = vec![false; 10];
+    assert!(matches!(
+        expression.eval_batch(&[Column::Float(&a), Column::Int(&b)], ColumnMut::Bool(&mut output)),
+        Err(Error::LengthMismatch)
+    ));
+    assert!(matches!(
+        expression.eval_batch(&[Column::Float(&a)], ColumnMut::Bool(&mut output)),
+        Err(Error::InvalidArgs)
+    ));
+}
+
+#[test]
+fn test_instruction_tape() {
+    let mut expression = Expression::<Std<1>, 1>::new("a - (2 - pow(b, 3))".to_string());
+    expression.compile().unwrap();
+    assert!(matches!(
+        expression.instructions(),
+        [
+            Instruction::Variable { index: 0 },
+            Instruction::Literal(_),
+            Instruction::Variable { index: 1 },
+            Instruction::Literal(_),
+            Instruction::Function { args: 2, .. },
+            Instruction::Binary { .. },
+            Instruction::Binary { .. },
+        ]
+    ));
+    expression.set_variable("a", Value::Single(Single::Int(10))).unwrap();
+    expression.set_variable("b", Value::Single(Single::Int(2))).unwrap();
+    assert_eq!(expression.eval().unwrap(), Value::Single(Single::Int(16)));
+    // the scratch stack is reused, also while other threads evaluate the same expression
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                for _ in 0..100 {
+                    assert_eq!(expression.eval().unwrap(), Value::Single(Single::Int(16)));
+                }
+            });
+        }
+    });
+
+    let mut expression = Expression::<Std<1>, 1>::new("-(1 + 2) * a".to_string());
+    expression.compile().unwrap();
+    expression.optimize().unwrap();
+    assert!(matches!(
+        expression.instructions(),
+        [
+            Instruction::Literal(Value::Single(Single::Int(-3))),
+            Instruction::Variable { index: 0 },
+            Instruction::Binary { .. },
+        ]
+    ));
+}
+
+#[test]
+fn test_program_threads() {
+    fn is_send_sync<T: Send + Sync>(_: &T) {}
+    let mut expression = Expression::<Std<4>, 4>::new("a * b + 1".to_string());
+    expression.compile().unwrap();
+    let program = Arc::new(expression.program().unwrap());
+    is_send_sync(&program);
+    let a = program.variable_index("a").unwrap();
+    let b = program.variable_index("b").unwrap();
+    std::thread::scope(|scope| {
+        for thread in 0..4 {
+            let program = Arc::clone(&program);
+            scope.spawn(move || {
+                let mut frame = program.frame();
+                for i in 0..100 {
+                    frame
+                        .set_variable_by_index(a, Value::Single(Single::Int(thread)))
+                        .unwrap();
+                    frame.set_variable_by_index(b, Value::Single(Single::Int(i))).unwrap();
+                    assert_eq!(
+                        program.eval(&mut frame).unwrap(),
+                        Value::Single(Single::Int(thread * i + 1))
+                    );
+                }
+            });
+        }
+    });
+    assert!(matches!(
+        Expression::<Std<4>, 4>::new("a".to_string()).program(),
+        Err(Error::NotCompiled)
+    ));
+}
+
+#[test]
+fn test_eval_batch_parallel() {
+    let a: Vec<f64> = (0..1003).map(|i| i as f64).collect();
+    let b: Vec<bool> = (0..1003).map(|i| i % 3 == 0).collect();
+    let mut expression = Expression::<Std<4>, 4>::new("a * 0.5 + b".to_string());
+    expression.compile().unwrap();
+    let mut expected = vec![0.0; 1003];
+    expression
+        .eval_batch(&[Column::Float(&a), Column::Bool(&b)], ColumnMut::Float(&mut expected))
+        .unwrap();
+    for threads in [0, 1, 3, 2000] {
+        let mut output = vec![0.0; 1003];
+        expression
+            .eval_batch_parallel(
+                &[Column::Float(&a), Column::Bool(&b)],
+                ColumnMut::Float(&mut output),
+                threads,
+            )
+            .unwrap();
+        assert_eq!(output, expected);
+    }
+    assert_eq!(expected[3], 2.5);
+    assert_eq!(expected[1002], 502.0);
+
+    // the panic of a worker reaches the caller with its own message
+    let mut functions = FunctionRegistry::<Std<4>, 4>::new();
+    functions.register("", "fail", 1, false, |_| panic!("fail was called"));
+    let mut expression =
+        Expression::<RegistryFunction<Std<4>, 4>, 4>::with_context("fail(a)".to_string(), functions);
+    expression.compile().unwrap();
+    let mut output = vec![0.0; 1003];
+    let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        expression.eval_batch_parallel(&[Column::Float(&a)], ColumnMut::Float(&mut output), 2)
+    }))
+    .unwrap_err();
+    assert_eq!(payload.downcast_ref::<&str>(), Some(&"fail was called"));
+}
+
+#[test]
+fn test_number_literals() {
+    assert_eq!(eval_single("1e-6"), Value::Single(Single::Float(1e-6)));
+    assert_eq!(eval_single("6.02E23"), Value::Single(Single::Float(6.02e23)));
+    assert_eq!(eval_single("2e3 + 1"), Value::Single(Single::Float(2001.0)));
+    assert_eq!(eval_single(".5e+1"), Value::Single(Single::Float(5.0)));
+    assert_eq!(eval_single("0xFF"), Value::Single(Single::Int(255)));
+    assert_eq!(eval_single("0x1e - 1"), Value::Single(Single::Int(29)));
+    assert_eq!(eval_single("0b1010"), Value::Single(Single::Int(10)));
+    assert_eq!(eval_single("1_000_000"), Value::Single(Single::Int(1_000_000)));
+    assert_eq!(eval_single("1_000.000_5"), Value::Single(Single::Float(1000.0005)));
+    assert_eq!(eval_single("0xFF_FF"), Value::Single(Single::Int(0xffff)));
+
+    for (expression, span) in [("1e", (0, 2)), ("2 + 0b102", (4, 9)), ("0x", (0, 2))] {
+        let mut expression = Expression::<Std<1>, 1>::new(expression.to_string());
+        let error = expression.compile().unwrap_err();
+        assert!(matches!(error, Error::InvalidToken(_)), "{:?}", error);
+        assert_eq!(error.span(), Some(Span::new(span.0, span.1)));
+    }
+}
+
+#[test]
+fn test_bool_literals() {
+    assert_eq!(eval_single("true"), Value::Single(Single::Bool(true)));
+    assert_eq!(eval_single("!false && true"), Value::Single(Single::Bool(true)));
+    assert_eq!(eval_single("1 > 2 == false"), Value::Single(Single::Bool(true)));
+
+    let mut expression = Expression::<Std<4>, 4>::new("a > 2 || false # true".to_string());
+    expression.compile().unwrap();
+    assert_eq!(expression.variables_iter().count(), 1);
+    expression
+        .set_variable("a", Value::Simd(Simd::Int([1, 2, 3, 4].into())))
+        .unwrap();
+    assert_eq!(
+        expression.eval().unwrap(),
+        Value::Simd(Simd::Bool([true, true, true, true].into()))
+    );
+    let mut expression = Expression::<Std<4>, 4>::new("a && true".to_string());
+    expression.compile().unwrap();
+    expression
+        .set_variable("a", Value::Simd(Simd::Bool([true, false, true, false].into())))
+        .unwrap();
+    expression.to_simd();
+    assert_eq!(
+        expression.eval().unwrap(),
+        Value::Simd(Simd::Bool([true, false, true, false].into()))
+    );
+}
+
+#[test]
+fn test_identifiers() {
+    let mut expression =
+        Expression::<Std<1>, 1>::new("max_speed * x_1 + température_C - _ + π2".to_string());
+    expression.compile().unwrap();
+    for (name, value) in [("max_speed", 2), ("x_1", 3), ("température_C", 20), ("_", 1), ("π2", 4)] {
+        expression.set_variable(name, Value::Single(Single::Int(value))).unwrap();
+    }
+    assert_eq!(expression.eval().unwrap(), Value::Single(Single::Int(29)));
+
+    let mut expression = Expression::<Std<1>, 1>::new("größe + 1 ~ 2".to_string());
+    let error = expression.compile().unwrap_err();
+    assert!(matches!(error, Error::UnkownCharacter('~', _)));
+    assert_eq!(error.span(), Some(Span::new(12, 13)));
+    // symbols that look like operators aren't part of a name
+    for (source, chr, span) in [
+        ("a×b", '×', Span::new(1, 3)),
+        ("a−b", '−', Span::new(1, 4)),
+        ("t°", '°', Span::new(1, 3)),
+    ] {
+        let error = Expression::<Std<1>, 1>::new(source.to_string()).compile().unwrap_err();
+        assert!(matches!(error, Error::UnkownCharacter(c, _) if c == chr), "{source}");
+        assert_eq!(error.span(), Some(span), "{source}");
+    }
+
+    // 16 and 18 bytes, on both sides of the inline limit
+    for name in ["ääääääää", "äääääääää"] {
+        let small = SmallString::<16>::new(name);
+        assert_eq!(small.as_str(), name);
+        assert_eq!(small, SmallString::<16>::from(name));
+    }
+}
+
+#[test]
+fn test_conditional() {
+    assert_eq!(eval_single("if(1 > 2, 10, 20)"), Value::Single(Single::Int(20)));
+    assert_eq!(eval_single("1 < 2 ? 1.5 : 2"), Value::Single(Single::Float(1.5)));
+    assert_eq!(eval_single("0 ? 1 : 0 ? 2 : 3"), Value::Single(Single::Int(3)));
+    assert_eq!(eval_single("1 ? 0 ? 4 : 5 : 6"), Value::Single(Single::Int(5)));
+    assert_eq!(eval_single("2 * if(true, 3, 4) + 1"), Value::Single(Single::Int(7)));
+
+    let mut expression = Expression::<Std<4>, 4>::new("a > 2 ? a * 10 : -a".to_string());
+    expression.compile().unwrap();
+    for (a, result) in [
+        ([1, 2, 3, 4], [-1, -2, 30, 40]),
+        ([5, 6, 7, 8], [50, 60, 70, 80]),
+        ([-1, 0, 1, 2], [1, 0, -1, -2]),
+    ] {
+        expression.set_variable("a", Value::Simd(Simd::Int(a.into()))).unwrap();
+        assert_eq!(expression.eval().unwrap(), Value::Simd(Simd::Int(result.into())));
+    }
+
+    assert_eq!(eval_single("1 ? std:sqrt(4) : 0"), Value::Single(Single::Float(2.0)));
+
+    // `x:y` is not a namespace here
+    let mut expression = Expression::<Std<4>, 4>::new("c ? x:y".to_string());
+    expression.compile().unwrap();
+    expression
+        .set_variable("c", Value::Simd(Simd::Bool([true, false, true, false].into())))
+        .unwrap();
+    expression.set_variable("x", Value::Single(Single::Float(0.5))).unwrap();
+    expression.set_variable("y", Value::Single(Single::Int(2))).unwrap();
+    assert_eq!(
+        expression.eval().unwrap(),
+        Value::Simd(Simd::Float([0.5, 2.0, 0.5, 2.0].into()))
+    );
+
+    // both branches run for simd conditions that differ between lanes,
+    // the lanes guarded against a zero divisor are computed but not selected
+    for source in ["if(b != 0, a / b, -1)", "b != 0 ? a / b : -1"] {
+        let mut expression = Expression::<Std<4>, 4>::new(source.to_string());
+        expression.compile().unwrap();
+        expression.set_variable("a", Value::Simd(Simd::Int([8, 8, 8, 8].into()))).unwrap();
+        expression.set_variable("b", Value::Simd(Simd::Int([0, 2, 0, 4].into()))).unwrap();
+        assert_eq!(
+            expression.eval().unwrap(),
+            Value::Simd(Simd::Int([-1, 4, -1, 2].into())),
+            "{source}"
+        );
+    }
+
+    // nor is `x:sqrt` when there's no other `:` for the `?`
+    let mut expression = Expression::<Std<1>, 1>::new("c ? x:sqrt(x)".to_string());
+    expression.compile().unwrap();
+    expression.set_variable("c", Value::Single(Single::Bool(false))).unwrap();
+    expression.set_variable("x", Value::Single(Single::Float(4.0))).unwrap();
+    assert_eq!(expression.eval().unwrap(), Value::Single(Single::Float(2.0)));
+    assert_eq!(eval_single("0 ? 1 ? 2 : 3 : std:sqrt(4)"), Value::Single(Single::Float(2.0)));
+    assert_eq!(eval_single("(1 ? std:sqrt(4) : 0) + 1"), Value::Single(Single::Float(3.0)));
+
+    for (expression, error) in [
+        ("if(1, 2)", Error::UnexpectedToken(Span::new(7, 8))),
+        ("1 ? 2", Error::UnexpectedEnd(Span::new(5, 5))),
+        ("1 ? 2 , 3", Error::UnexpectedToken(Span::new(6, 7))),
+    ] {
+        let mut expression = Expression::<Std<1>, 1>::new(expression.to_string());
+        let compile_error = expression.compile().unwrap_err();
+        assert_eq!(std::mem::discriminant(&compile_error), std::mem::discriminant(&error));
+        assert_eq!(compile_error.span(), error.span());
+    }
+}
+
+#[test]
+fn test_short_circuit() {
+    // the division by zero would panic if the right side was evaluated
+    let mut expression = Expression::<Std<1>, 1>::new("b != 0 && a / b > 1".to_string());
+    expression.compile().unwrap();
+    expression.set_variable("a", Value::Single(Single::Int(4))).unwrap();
+    expression.set_variable("b", Value::Single(Single::Int(0))).unwrap();
+    assert_eq!(expression.eval().unwrap(), Value::Single(Single::Bool(false)));
+    expression.set_variable("b", Value::Single(Single::Int(2))).unwrap();
+    assert_eq!(expression.eval().unwrap(), Value::Single(Single::Bool(true)));
+
+    assert_eq!(eval_single("1 || 1 / 0"), Value::Single(Single::Bool(true)));
+    assert_eq!(eval_single("0 || 2 > 1"), Value::Single(Single::Bool(true)));
+    assert_eq!(eval_single("0 && 1 / 0 || 1"), Value::Single(Single::Bool(true)));
+    let mut expression = Expression::<Std<1>, 1>::new("a + (0 && 1 / 0)".to_string());
+    expression.compile().unwrap();
+    expression.optimize().unwrap();
+    assert!(matches!(
+        expression.instructions(),
+        [
+            Instruction::Variable { .. },
+            Instruction::Literal(Value::Single(Single::Bool(false))),
+            Instruction::Binary { .. }
+        ]
+    ));
+
+    let mut expression = Expression::<Std<4>, 4>::new("b == 0 || a / b > 1".to_string());
+    expression.compile().unwrap();
+    expression.set_variable("a", Value::Simd(Simd::Int([4, 1, 4, 1].into()))).unwrap();
+    expression.set_variable("b", Value::Simd(Simd::Int([0; 4].into()))).unwrap();
+    assert_eq!(expression.eval().unwrap(), Value::Simd(Simd::Bool([true; 4].into())));
+    expression.set_variable("b", Value::Simd(Simd::Int([2, 2, 1, 1].into()))).unwrap();
+    assert_eq!(
+        expression.eval().unwrap(),
+        Value::Simd(Simd::Bool([true, false, true, false].into()))
+    );
+
+    // with mixed lanes the right side runs on every lane, the zero divisors don't panic
+    expression.set_variable("b", Value::Simd(Simd::Int([0, 2, 0, 1].into()))).unwrap();
+    assert_eq!(
+        expression.eval().unwrap(),
+        Value::Simd(Simd::Bool([true, false, true, false].into()))
+    );
+    let mut expression = Expression::<Std<4>, 4>::new("b != 0 && a / b > 1".to_string());
+    expression.compile().unwrap();
+    expression.set_variable("a", Value::Simd(Simd::Int([4, 1, 4, 1].into()))).unwrap();
+    expression.set_variable("b", Value::Simd(Simd::Int([0, 2, 1, 0].into()))).unwrap();
+    assert_eq!(
+        expression.eval().unwrap(),
+        Value::Simd(Simd::Bool([false, false, true, false].into()))
+    );
+}
+
+#[test]
+fn test_variadic_functions() {
+    assert_eq!(eval_single("min(3, 1, 2)"), Value::Single(Single::Int(1)));
+    assert_eq!(eval_single("max(3, 1.5, 2)"), Value::Single(Single::Float(3.0)));
+    assert_eq!(eval_single("max(7)"), Value::Single(Single::Int(7)));
+    assert_eq!(eval_single("sum(1, 2, 3, 4)"), Value::Single(Single::Int(10)));
+    assert_eq!(eval_single("mean(1, 2, 3, 4)"), Value::Single(Single::Float(2.5)));
+    assert_eq!(eval_single("hypot(3, 4)"), Value::Single(Single::Float(5.0)));
+    assert_eq!(eval_single("hypot(2, 3, 6)"), Value::Single(Single::Float(7.0)));
+    // the squares of large arguments and of ints above `sqrt(i64::MAX)` don't overflow
+    for (x, y) in [(3e200f64, 4e200), (3e-200, 4e-200), (3037000500.0, 0.0), (0.0, 0.0)] {
+        let Value::Single(Single::Float(result)) = eval_single(&format!("hypot({x:e}, {y:e})"))
+        else {
+            panic!("hypot({x}, {y}) isn't a float");
+        };
+        assert!(ulps(result, x.hypot(y)) <= 1, "hypot({x}, {y}) = {result}");
+    }
+    assert_eq!(
+        eval_single("hypot(3037000500, 0)"),
+        Value::Single(Single::Float(3037000500.0))
+    );
+    let mut expression = Expression::<Std<4>, 4>::new("hypot(a, 4e200)".to_string());
+    expression.compile().unwrap();
+    let a = [3e200, 0.0, -3e200, 1e300];
+    expression.set_variable("a", Value::Simd(Simd::Float(a.into()))).unwrap();
+    let Value::Simd(Simd::Float(result)) = expression.eval().unwrap() else {
+        panic!("hypot of a simd value isn't a simd float");
+    };
+    for (a, result) in a.iter().zip(result.to_array()) {
+        assert!(ulps(result, a.hypot(4e200)) <= 1, "hypot({a}, 4e200) = {result}");
+    }
+
+    let mut expression = Expression::<Std<4>, 4>::new("min(a, 2, b)".to_string());
+    expression.compile().unwrap();
+    expression.set_variable("a", Value::Simd(Simd::Int([1, 2, 3, 4].into()))).unwrap();
+    expression.set_variable("b", Value::Simd(Simd::Int([4, 3, 1, 0].into()))).unwrap();
+    assert_eq!(expression.eval().unwrap(), Value::Simd(Simd::Int([1, 2, 1, 0].into())));
+
+    for (expression, span) in [("sqrt(1, 2)", Span::new(0, 4)), ("1 + min()", Span::new(4, 7)), ("hypot(1)", Span::new(0, 5))] {
+        let mut expression = Expression::<Std<1>, 1>::new(expression.to_string());
+        let error = expression.compile().unwrap_err();
+        assert!(matches!(error, Error::InvalidArgCount(_)));
+        assert_eq!(error.span(), Some(span));
+    }
+}
+
+#[test]
+fn test_function_registry() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let mut functions = FunctionRegistry::<Std<4>, 4>::new();
+    functions
+        .register("app", "double", 1, true, |args| Ok(args[0] + args[0]))
+        .register("", "clamp", 3, true, |args| Ok(args[0].max(args[1]).min(args[2])))
+        .register("app:io", "count", 0.., false, move |args| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            Ok(Value::Single(Single::Int(args.len() as i64)))
+        });
+    assert!(functions.contains("app:io", "count"));
+
+    let mut expression = Expression::<RegistryFunction<Std<4>, 4>, 4>::with_context(
+        "app:double(x) + clamp(x, 1, 2) + sqrt(4) + app:io:count(1, 2)".to_string(),
+        functions,
+    );
+    expression.compile().unwrap();
+    expression.optimize().unwrap();
+    // the impure function is not evaluated ahead of time
+    assert_eq!(calls.load(Ordering::Relaxed), 0);
+    expression.set_variable("x", Value::Simd(Simd::Int([0, 1, 2, 3].into()))).unwrap();
+    assert_eq!(
+        expression.eval().unwrap(),
+        Value::Simd(Simd::Float([5.0, 7.0, 10.0, 12.0].into()))
+    );
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+    // programs using registered closures can still be shared between threads
+    let program = Arc::new(expression.program().unwrap());
+    std::thread::scope(|scope| {
+        for _ in 0..2 {
+            let program = program.clone();
+            scope.spawn(move || program.eval(&mut program.frame()).unwrap());
+        }
+    });
+    assert_eq!(calls.load(Ordering::Relaxed), 3);
+
+    for (string, error) in [
+        ("app:double(1, 2)", Error::InvalidArgCount(Span::new(4, 10))),
+        ("double(1)", Error::NoIdentifierMatch(Span::new(0, 6))),
+    ] {
+        let mut expression = Expression::<RegistryFunction<Std<4>, 4>, 4>::new(string.to_string());
+        *expression.context_mut() = FunctionRegistry::new();
+        expression.context_mut().register("app", "double", 1, true, |args| Ok(args[0]));
+        let compile_error = expression.compile().unwrap_err();
+        assert_eq!(std::mem::discriminant(&compile_error), std::mem::discriminant(&error));
+        assert_eq!(compile_error.span(), error.span());
+    }
+
+    // calls of the same registration are shared, calls of different ones with the same body aren't
+    let mut functions = FunctionRegistry::<Std<1>, 1>::new();
+    functions
+        .register("a", "id", 1, true, |args| Ok(args[0]))
+        .register("b", "id", 1, true, |args| Ok(args[0]));
+    let mut expression = Expression::<RegistryFunction<Std<1>, 1>, 1>::with_context(
+        "a:id(x) * a:id(x) + b:id(x) + sin(x) + cos(x)".to_string(),
+        functions,
+    );
+    expression.compile().unwrap();
+    let calls = expression
+        .instructions()
+        .iter()
+        .filter(|instruction| matches!(instruction, Instruction::Function { .. }))
+        .count();
+    assert_eq!(calls, 4);
+}
+
+#[test]
+fn test_define() {
+    let mut expression = Expression::<Std<4>, 4>::new("f(a, 2) * g(a) + sq(3)".to_string());
+    expression.define("f(x, y) = x^2 + y").unwrap();
+    // bodies can call earlier definitions and use the variables of the caller
+    expression.define("g(x) = f(x, 1) - offset").unwrap();
+    expression.define("sq(x) = sqrt(x * x)").unwrap();
+    expression.compile().unwrap();
+    expression.set_variable("a", Value::Simd(Simd::Int([0, 1, 2, 3].into()))).unwrap();
+    expression.set_variable("offset", Value::Single(Single::Int(1))).unwrap();
+    assert_eq!(
+        expression.eval().unwrap(),
+        Value::Simd(Simd::Float([3.0, 6.0, 27.0, 102.0].into()))
+    );
+    // the calls are inlined, only `sqrt` is left as a function call
+    let calls = expression
+        .instructions()
+        .iter()
+        .filter(|instruction| matches!(instruction, Instruction::Function { .. }))
+        .count();
+    assert_eq!(calls, 1);
+
+    // definitions are kept for the next expression and can be replaced
+    expression.set_expression("f(2, 1) + std:sqrt(4)".to_string());
+    expression.define("f(x, y) = x - y").unwrap();
+    expression.compile().unwrap();
+    expression.optimize().unwrap();
+    assert_eq!(expression.eval().unwrap(), Value::Single(Single::Float(3.0)));
+
+    for (definition, error) in [
+        ("f(x) = f(x - 1)", Error::RecursiveDefinition(Span::new(7, 8))),
+        ("f(x, x) = x", Error::UnexpectedToken(Span::new(5, 6))),
+        ("f(x) == x", Error::UnexpectedToken(Span::new(5, 7))),
+        ("f(x) =", Error::UnexpectedEnd(Span::new(6, 6))),
+        ("f(x) = x +", Error::UnexpectedEnd(Span::new(10, 10))),
+        ("x = 1", Error::UnexpectedToken(Span::new(0, 1))),
+    ] {
+        let mut expression = Expression::<Std<1>, 1>::new(String::new());
+        let define_error = expression.define(definition).unwrap_err();
+        assert_eq!(std::mem::discriminant(&define_error), std::mem::discriminant(&error));
+        assert_eq!(define_error.span(), error.span());
+    }
+
+    let mut expression = Expression::<Std<1>, 1>::new("h(1)".to_string());
+    expression.define("h(x, y) = x + y").unwrap();
+    assert_eq!(expression.compile().unwrap_err().span(), Some(Span::new(0, 1)));
+}
+
+#[test]
+fn test_script() {
+    let mut expression = Expression::<Std<1>, 1>::new(
+        "let t = a * 2;\n let u = t + 1;\n out = t - b;\n u ^ 2".to_string(),
+    );
+    expression.compile().unwrap();
+    expression.set_variable("a", Value::Single(Single::Int(3))).unwrap();
+    expression.set_variable("b", Value::Single(Single::Int(1))).unwrap();
+    // `eval` leaves the expression as it is, `eval_assign` keeps the assigned values
+    assert_eq!(expression.eval().unwrap(), Value::Single(Single::Int(49)));
+    assert_eq!(expression.variable("out").unwrap(), Value::Single(Single::Int(0)));
+    assert_eq!(expression.eval_assign().unwrap(), Value::Single(Single::Int(49)));
+    assert_eq!(expression.variable("out").unwrap(), Value::Single(Single::Int(5)));
+    assert_eq!(expression.variable("t").unwrap(), Value::Single(Single::Int(6)));
+    // `==` still compares, a trailing `;` is allowed
+    assert_eq!(eval_single("x = 2; x == 2;"), Value::Single(Single::Bool(true)));
+    // `=!` after the name of a statement assigns a negation, elsewhere it is `!=`
+    let mut negation = Expression::<Std<1>, 1>::new("out=!b; let t=!out; t =! out".to_string());
+    negation.compile().unwrap();
+    negation.set_variable("b", Value::Single(Single::Bool(false))).unwrap();
+    assert_eq!(negation.eval_assign().unwrap(), Value::Single(Single::Bool(false)));
+    assert_eq!(negation.variable("out").unwrap(), Value::Single(Single::Bool(true)));
+    assert_eq!(eval_single("x = 1; x =! 1"), Value::Single(Single::Bool(false)));
+    assert_eq!(eval_single("x = 1; 2 =! x"), Value::Single(Single::Bool(true)));
+    // without a `;` a single `=` compares too
+    let mut comparison = Expression::<Std<1>, 1>::new("a = 1".to_string());
+    comparison.compile().unwrap();
+    comparison.set_variable("a", Value::Single(Single::Int(2))).unwrap();
+    assert_eq!(comparison.eval().unwrap(), Value::Single(Single::Bool(false)));
+    assert_eq!(comparison.variable("a").unwrap(), Value::Single(Single::Int(2)));
+
+    // assigned variables come after the inputs, so batches only take columns for `a` and `b`
+    let program = expression.program().unwrap();
+    assert_eq!(program.input_count(), 2);
+    let mut frame = program.frame();
+    let a = program.variable_index("a").unwrap();
+    frame.set_variable_by_index(a, Value::Single(Single::Int(1))).unwrap();
+    program.eval(&mut frame).unwrap();
+    let out = frame.variable(program.variable_index("out").unwrap()).unwrap();
+    assert_eq!(out, Value::Single(Single::Int(1)));
+    let (a, b) = ([1, 2, 3, 4, 5], [0, 0, 1, 1, 1]);
+    let mut output = [0; 5];
+    expression
+        .eval_batch(&[Column::Int(&a), Column::Int(&b)], ColumnMut::Int(&mut output))
+        .unwrap();
+    assert_eq!(output, [9, 25, 49, 81, 121]);
+
+    for (script, error) in [
+        ("a + 1; let a = 2; a", Error::Redeclaration(Span::new(11, 12))),
+        ("let x = 1; let x = 2; x", Error::Redeclaration(Span::new(15, 16))),
+        ("let x + 1", Error::UnexpectedToken(Span::new(6, 7))),
+        ("x = 1;; x", Error::UnexpectedToken(Span::new(6, 7))),
+        ("out = ", Error::UnexpectedEnd(Span::new(6, 6))),
+        ("let t = t + 1; t", Error::ReadBeforeAssignment(Span::new(8, 9))),
+        ("out = out + a;", Error::ReadBeforeAssignment(Span::new(6, 9))),
+        ("let t = u; u = 1; t", Error::ReadBeforeAssignment(Span::new(8, 9))),
+    ] {
+        let mut expression = Expression::<Std<1>, 1>::new(script.to_string());
+        let compile_error = expression.compile().unwrap_err();
+        assert_eq!(std::mem::discriminant(&compile_error), std::mem::discriminant(&error));
+        assert_eq!(compile_error.span(), error.span());
+    }
+}
+
+#[test]
+fn test_tuple() {
+    let mut expression =
+        Expression::<Std<4>, 4>::new("let s = a * b; (a + b, s + 1, s - 1)".to_string());
+    expression.compile().unwrap();
+    assert_eq!(expression.result_count(), 3);
+    expression.set_variable("a", Value::Simd(Simd::Int([1, 2, 3, 4].into()))).unwrap();
+    expression.set_variable("b", Value::Single(Single::Int(2))).unwrap();
+    let mut results = [Value::Single(Single::Int(0)); 3];
+    expression.eval_tuple(&mut results).unwrap();
+    assert_eq!(
+        results,
+        [
+            Value::Simd(Simd::Int([3, 4, 5, 6].into())),
+            Value::Simd(Simd::Int([3, 5, 7, 9].into())),
+            Value::Simd(Simd::Int([1, 3, 5, 7].into())),
+        ]
+    );
+    assert!(matches!(expression.eval(), Err(Error::ResultCount)));
+    assert!(matches!(expression.eval_tuple(&mut results[..2]), Err(Error::ResultCount)));
+
+    // one output column per item, including the rows that don't fill a vector
+    let a: Vec<f64> = (0..10).map(|row| row as f64).collect();
+    let b = [2.0; 10];
+    let (mut sum, mut up, mut down) = ([0.0; 10], [0; 10], [0.0; 10]);
+    let inputs = [Column::Float(&a), Column::Float(&b)];
+    let mut outputs = [
+        ColumnMut::Float(&mut sum),
+        ColumnMut::Int(&mut up),
+        ColumnMut::Float(&mut down),
+    ];
+    expression.eval_batch_tuple(&inputs, &mut outputs).unwrap();
+    assert_eq!(sum, [2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0]);
+    assert_eq!(up, [1, 3, 5, 7, 9, 11, 13, 15, 17, 19]);
+    assert_eq!(down[9], 17.0);
+    let mut parallel = [0.0; 10];
+    let mut outputs = [
+        ColumnMut::Float(&mut parallel),
+        ColumnMut::Int(&mut up),
+        ColumnMut::Float(&mut down),
+    ];
+    expression.eval_batch_tuple_parallel(&inputs, &mut outputs, 3).unwrap();
+    assert_eq!(parallel, sum);
+    assert!(matches!(
+        expression.eval_batch(&inputs, ColumnMut::Float(&mut sum)),
+        Err(Error::ResultCount)
+    ));
+
+    for (string, span) in [
+        ("(1, 2) + 1", Span::new(0, 1)),
+        ("x = (1, 2); x", Span::new(4, 5)),
+        ("((1, 2), 3)", Span::new(1, 2)),
+    ] {
+        let mut expression = Expression::<Std<1>, 1>::new(string.to_string());
+        let error = expression.compile().unwrap_err();
+        assert!(matches!(error, Error::UnexpectedToken(_)));
+        assert_eq!(error.span(), Some(span));
+    }
+}
+
+#[test]
+fn test_constant_folding() {
+    let mut expression = Expression::<Std<1>, 1>::new("sqrt(16) * log(8, 2)".to_string());
+    expression.compile().unwrap();
+    expression.optimize().unwrap();
+    assert!(matches!(
+        expression.instructions(),
+        [Instruction::Literal(Value::Single(Single::Float(value)))] if (value - 12.0).abs() < 1e-12
+    ));
+    // only the literal is left of the tree
+    assert_eq!(expression.elements().len(), 1);
+
+    // the constant arguments of other calls are still folded
+    let mut expression =
+        Expression::<Std<1>, 1>::new("max(a, sqrt(sqrt(16)) + 1) + print(2 * 3)".to_string());
+    expression.compile().unwrap();
+    expression.optimize().unwrap();
+    assert!(matches!(
+        expression.instructions(),
+        [
+            Instruction::Variable { .. },
+            Instruction::Literal(Value::Single(Single::Float(3.0))),
+            Instruction::Function { args: 2, .. },
+            Instruction::Literal(Value::Single(Single::Int(6))),
+            Instruction::Function { args: 1, .. },
+            Instruction::Binary { .. },
+        ]
+    ));
+    assert_eq!(expression.elements().len(), 6);
+
+    // a constant condition leaves only the branch it takes
+    let mut expression = Expression::<Std<1>, 1>::new("if(1 > 2, a, b * 2)".to_string());
+    expression.compile().unwrap();
+    expression.optimize().unwrap();
+    assert!(matches!(
+        expression.instructions(),
+        [Instruction::Variable { .. }, Instruction::Literal(_), Instruction::Binary { .. }]
+    ));
+    expression.set_variable("b", Value::Single(Single::Int(4))).unwrap();
+    assert_eq!(expression.eval().unwrap(), Value::Single(Single::Int(8)));
+}
+
+#[test]
+fn test_simplify() {
+    let optimized = |expression: &str, level| {
+        let mut expression = Expression::<Std<1>, 1>::new(expression.to_string());
+        expression.compile().unwrap();
+        expression.optimize_with(level).unwrap();
+        expression
+    };
+    use OptimizationLevel::{FastMath, Safe};
+    for source in ["a * 1", "1 * a", "a / 1", "a - 0", "--a", "a ^ 1", "(a - 0 * 1) * 1"] {
+        assert!(
+            matches!(optimized(source, Safe).instructions(), [Instruction::Variable { .. }]),
+            "{source}"
+        );
+    }
+    // `a + 0` turns `-0.0` into `0.0` and `a * 0` is `NaN` for infinite floats
+    for source in ["a + 0", "a * 0.0", "a - a"] {
+        assert_eq!(optimized(source, Safe).instructions().len(), 3, "{source}");
+    }
+    assert!(matches!(
+        optimized("a + 0", FastMath).instructions(),
+        [Instruction::Variable { .. }]
+    ));
+    // the type of a variable isn't known, but comparisons are bools and bools times ints are ints
+    assert!(matches!(
+        optimized("(a > b) * 2 - (a > b) * 2 + if(c, 2, 3) ^ 0", Safe).instructions(),
+        [Instruction::Literal(Value::Single(Single::Float(1.0)))]
+    ));
+    assert!(matches!(
+        optimized("(a > b) * 0", Safe).instructions(),
+        [Instruction::Literal(Value::Single(Single::Int(0)))]
+    ));
+    assert!(matches!(
+        optimized("a * 0.0 + (c ? 1.5 : 2.5) - (c ? 1.5 : 2.5)", FastMath).instructions(),
+        [Instruction::Literal(Value::Single(Single::Float(0.0)))]
+    ));
+    // bare variables have no known type, the zero could be an int, a float or `NaN`
+    for source in ["1 / (a - a)", "1 / (a * 0)", "1 / (0 * a)"] {
+        let mut expression = optimized(source, FastMath);
+        assert_eq!(expression.instructions().len(), 5, "{source}");
+        expression.set_variable("a", Value::Single(Single::Float(1.5))).unwrap();
+        assert_eq!(expression.eval().unwrap(), Value::Single(Single::Float(f64::INFINITY)));
+    }
+    // calls might not be pure
+    assert_eq!(optimized("print(1) - print(1)", FastMath).instructions().len(), 5);
+
+    // constant chains are re-associated, exactly for ints
+    let mut expression = optimized("2 * a * 3", FastMath);
+    assert!(matches!(
+        expression.instructions(),
+        [
+            Instruction::Literal(Value::Single(Single::Int(6))),
+            Instruction::Variable { .. },
+            Instruction::Binary { .. },
+        ]
+    ));
+    expression.set_variable("a", Value::Single(Single::Float(1.5))).unwrap();
+    assert_eq!(expression.eval().unwrap(), Value::Single(Single::Float(9.0)));
+    assert_eq!(optimized("2 * a * 3", Safe).instructions().len(), 5);
+    assert!(matches!(
+        optimized("1 + ((a > 0) + 2) + 3", Safe).instructions(),
+        [
+            Instruction::Literal(Value::Single(Single::Int(6))),
+            Instruction::Variable { .. },
+            Instruction::Literal(Value::Single(Single::Int(0))),
+            Instruction::Binary { .. },
+            Instruction::Binary { .. },
+        ]
+    ));
+    // a chain that becomes `1 * a` is simplified further
+    assert_eq!(
+        optimized("(c ? 2.5 : 1.5) * 2 * 0.5", FastMath).instructions().len(),
+        optimized("c ? 2.5 : 1.5", Safe).instructions().len()
+    );
+}
+
+#[test]
+fn test_common_subexpressions() {
+    let compiled = |expression: &str| {
+        let mut expression = Expression::<Std<1>, 1>::new(expression.to_string());
+        expression.compile().unwrap();
+        expression
+    };
+    let calls = |expression: &Expression<Std<1>, 1>| {
+        expression
+            .instructions()
+            .iter()
+            .filter(|instruction| matches!(instruction, Instruction::Function { .. }))
+            .count()
+    };
+    let mut expression = compiled("sqrt(a^2 + b^2) * 2 + sqrt(a^2 + b^2) / sqrt(b^2 + a^2)");
+    // the second `sqrt` and the squares of the third are loaded from temporaries
+    assert_eq!(calls(&expression), 2);
+    assert_eq!(expression.optimize().unwrap(), 3);
+    expression.set_variable("a", Value::Single(Single::Int(3))).unwrap();
+    expression.set_variable("b", Value::Single(Single::Int(4))).unwrap();
+    assert_eq!(expression.eval().unwrap(), Value::Single(Single::Float(11.0)));
+    // the temporaries are part of the frame, not of the named variables
+    let program = expression.program().unwrap();
+    assert_eq!(program.input_count(), 2);
+    assert_eq!(program.variables_iter().count(), 2);
+    let mut frame = program.frame();
+    frame.set_variable_by_index(0, Value::Single(Single::Int(6))).unwrap();
+    frame.set_variable_by_index(1, Value::Single(Single::Int(8))).unwrap();
+    assert_eq!(program.eval(&mut frame).unwrap(), Value::Single(Single::Float(21.0)));
+
+    // a value computed in a branch that might not be taken is computed again
+    assert_eq!(compiled("(c ? sqrt(a) : 0) + sqrt(a)").optimize().unwrap(), 0);
+    assert_eq!(compiled("sqrt(a) + (c ? sqrt(a) : 0)").optimize().unwrap(), 1);
+    assert_eq!(compiled("a > 0 && sqrt(a) > 1 || sqrt(a) < 2").optimize().unwrap(), 0);
+    // calls that aren't pure and variables that are assigned aren't shared
+    assert_eq!(calls(&compiled("print(a) + print(a)")), 2);
+    // only `a * 2` is shared, `t` changes between the statements
+    let mut expression = compiled("let t = a * 2; t = t + a * 2; (t * 3, t * 3)");
+    assert_eq!(expression.optimize().unwrap(), 1);
+    expression.set_variable("a", Value::Single(Single::Int(1))).unwrap();
+    let mut results = [Value::Single(Single::Int(0)); 2];
+    expression.eval_tuple(&mut results).unwrap();
+    assert_eq!(results, [Value::Single(Single::Int(12)); 2]);
+    // the arguments of definitions are evaluated once
+    let mut expression = Expression::<Std<1>, 1>::new("f(sqrt(a))".to_string());
+    expression.define("f(x) = x * x + x").unwrap();
+    expression.compile().unwrap();
+    assert_eq!(calls(&expression), 1);
+
+    // every level shares the one below, the passes have to visit each node once
+    let depth = 40;
+    let source = format!("{}a{}", "f(".repeat(depth), ")".repeat(depth));
+    let mut expression = Expression::<Std<1>, 1>::new(source);
+    expression.define("f(x) = x + x - 0").unwrap();
+    expression.compile().unwrap();
+    assert_eq!(expression.optimize_with(OptimizationLevel::FastMath).unwrap(), depth - 1);
+    assert!(expression.instructions().len() <= 4 * depth);
+    expression.set_variable("a", Value::Single(Single::Int(1))).unwrap();
+    assert_eq!(expression.eval().unwrap(), Value::Single(Single::Int(1 << depth)));
+}
+
+#[test]
+fn test_strength_reduction() {
+    let optimized = |expression: &str, level| {
+        let mut expression = Expression::<Std<4>, 4>::new(expression.to_string());
+        expression.compile().unwrap();
+        expression.optimize_with(level).unwrap();
+        expression
+    };
+    let count = |expression: &Expression<Std<4>, 4>, pattern: fn(&Instruction<Std<4>, 4>) -> bool| {
+        expression
+            .instructions()
+            .iter()
+            .filter(|instruction| pattern(instruction))
+            .count()
+    };
+    let values = [
+        Value::Simd(Simd::Float([-1.5, 0.1, 3.0, 1e10].into())),
+        Value::Simd(Simd::Int([-3, 0, 2, 5].into())),
+        Value::Single(Single::Float(1.1)),
+    ];
+    // `x^7` is `x * x^2 * x^4` with the squares shared, like `powi` computes it
+    for (source, multiplications) in [("x^2", 1), ("x^7", 4), ("x^16", 4)] {
+        let mut expression = optimized(source, OptimizationLevel::Safe);
+        assert_eq!(
+            count(&expression, |instruction| matches!(instruction, Instruction::Binary { .. })),
+            multiplications,
+            "{source}"
+        );
+        let mut reference = Expression::<Std<4>, 4>::new(source.to_string());
+        reference.compile().unwrap();
+        for value in values {
+            expression.set_variable("x", value).unwrap();
+            reference.set_variable("x", value).unwrap();
+            assert_eq!(expression.eval().unwrap(), reference.eval().unwrap(), "{source}");
+        }
+    }
+    // exponents that are too big or not ints and bools stay powers
+    for source in ["x^17", "x^-2", "x^2.0", "(x > 1)^2", "x^0.5"] {
+        assert!(
+            matches!(
+                optimized(source, OptimizationLevel::Safe).instructions().last(),
+                Some(Instruction::Binary { .. })
+            ),
+            "{source}"
+        );
+    }
+
+    // `sqrt` and `mul_add` differ from the power and the rounded product in their last bit
+    let mut expression = optimized("x^0.5 + a * b + c * x^2", OptimizationLevel::FastMath);
+    assert_eq!(count(&expression, |instruction| matches!(instruction, Instruction::MulAdd)), 2);
+    assert_eq!(
+        count(&expression, |instruction| matches!(instruction, Instruction::Unary { .. })),
+        1
+    );
+    assert_eq!(
+        count(&expression, |instruction| matches!(instruction, Instruction::Binary { .. })),
+        1
+    );
+    expression.set_variable("x", Value::Single(Single::Float(4.0))).unwrap();
+    expression.set_variable("a", Value::Single(Single::Int(2))).unwrap();
+    expression.set_variable("b", Value::Single(Single::Float(0.25))).unwrap();
+    expression.set_variable("c", Value::Simd(Simd::Int([0, 1, 2, 3].into()))).unwrap();
+    assert_eq!(
+        expression.eval().unwrap(),
+        Value::Simd(Simd::Float([2.5, 18.5, 34.5, 50.5].into()))
+    );
+    let expression = optimized("a * b + c", OptimizationLevel::Safe);
+    assert_eq!(count(&expression, |instruction| matches!(instruction, Instruction::MulAdd)), 0);
+}
+
+#[test]
+fn test_specialize() {
+    let mut expression =
+        Expression::<Std<4>, 4>::new("k * x^2 + offset * y - sqrt(k * 8)".to_string());
+    expression.compile().unwrap();
+    let mut specialized = expression
+        .specialize(&[
+            ("k", Value::Single(Single::Int(2))),
+            ("offset", Value::Single(Single::Int(0))),
+        ])
+        .unwrap();
+    let mut variables = specialized.variables_iter().map(|(name, _)| name).collect::<Vec<_>>();
+    variables.sort();
+    assert_eq!(variables, ["x", "y"]);
+    // `offset * y` is gone and `sqrt(16)` is folded
+    assert!(!specialized
+        .instructions()
+        .iter()
+        .any(|instruction| matches!(instruction, Instruction::Function { .. })));
+    let x = Value::Simd(Simd::Int([0, 1, 2, 3].into()));
+    specialized.set_variable("x", x).unwrap();
+    specialized.set_variable("y", Value::Single(Single::Int(5))).unwrap();
+    expression.set_variable("x", x).unwrap();
+    expression.set_variable("k", Value::Single(Single::Int(2))).unwrap();
+    assert_eq!(specialized.eval().unwrap(), expression.eval().unwrap());
+    assert_eq!(specialized.program().unwrap().input_count(), 2);
+
+    // assigned variables keep working and stay behind the inputs
+    let mut expression =
+        Expression::<Std<1>, 1>::new("let t = k * x; out = t + k; t * 2".to_string());
+    expression.compile().unwrap();
+    let mut specialized = expression.specialize(&[("k", Value::Single(Single::Int(3)))]).unwrap();
+    assert_eq!(specialized.program().unwrap().input_count(), 1);
+    specialized.set_variable("x", Value::Single(Single::Int(2))).unwrap();
+    assert_eq!(specialized.eval_assign().unwrap(), Value::Single(Single::Int(12)));
+    assert_eq!(specialized.variable("out").unwrap(), Value::Single(Single::Int(9)));
+
+    // the bound variable doesn't have to be the first one and the assigned ones in between
+    // are moved behind the inputs again
+    let mut expression =
+        Expression::<Std<1>, 1>::new("out = c - a; let t = b * 10; a + t + c * 100".to_string());
+    expression.compile().unwrap();
+    for name in ["a", "b", "c"] {
+        let mut specialized =
+            expression.specialize(&[(name, Value::Single(Single::Int(7)))]).unwrap();
+        assert_eq!(specialized.program().unwrap().input_count(), 2);
+        for (other, value) in [("a", 1), ("b", 2), ("c", 3)] {
+            let value = Value::Single(Single::Int(if other == name { 7 } else { value }));
+            expression.set_variable(other, value).unwrap();
+            if other != name {
+                specialized.set_variable(other, value).unwrap();
+            }
+        }
+        assert_eq!(specialized.eval_assign().unwrap(), expression.eval_assign().unwrap());
+        assert_eq!(specialized.variable("out").unwrap(), expression.variable("out").unwrap());
+    }
+
+    // binding every input leaves only the assigned variables
+    let mut specialized = expression
+        .specialize(&[
+            ("a", Value::Single(Single::Int(1))),
+            ("b", Value::Single(Single::Int(2))),
+            ("c", Value::Single(Single::Int(3))),
+        ])
+        .unwrap();
+    assert_eq!(specialized.program().unwrap().input_count(), 0);
+    assert_eq!(specialized.eval_assign().unwrap(), Value::Single(Single::Int(321)));
+    assert_eq!(specialized.variable("out").unwrap(), Value::Single(Single::Int(2)));
+
+    let bind = |name| [(name, Value::Single(Single::Int(1)))];
+    assert!(matches!(expression.specialize(&bind("out")), Err(Error::InvalidVariable)));
+    assert!(matches!(expression.specialize(&bind("z")), Err(Error::InvalidVariable)));
+    let expression = Expression::<Std<1>, 1>::new("a".to_string());
+    assert!(matches!(expression.specialize(&bind("a")), Err(Error::NotCompiled)));
+}